@@ -0,0 +1,111 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+/// Usage of a single inbox or outbox.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, RustcDecodable, RustcEncodable)]
+pub struct BoxUsage {
+    bytes_used: u64,
+    message_count: u64,
+    limit: u64,
+}
+
+impl BoxUsage {
+    /// Constructor.  `limit` is the maximum number of bytes the box may hold, e.g.
+    /// [`MAX_INBOX_SIZE`](constant.MAX_INBOX_SIZE.html).
+    pub fn new(bytes_used: u64, message_count: u64, limit: u64) -> BoxUsage {
+        BoxUsage {
+            bytes_used: bytes_used,
+            message_count: message_count,
+            limit: limit,
+        }
+    }
+
+    /// Getter for the number of bytes used.
+    pub fn bytes_used(&self) -> u64 {
+        self.bytes_used
+    }
+
+    /// Getter for the number of messages (or headers, for an inbox) held.
+    pub fn message_count(&self) -> u64 {
+        self.message_count
+    }
+
+    /// Getter for the maximum number of bytes the box may hold.
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// The number of bytes still available.
+    pub fn remaining(&self) -> u64 {
+        self.limit.saturating_sub(self.bytes_used)
+    }
+
+    /// Whether an item of `size` bytes would currently fit.
+    pub fn can_accept(&self, size: u64) -> bool {
+        size <= self.remaining()
+    }
+}
+
+/// Usage of an account's inbox and outbox, sent by MpidManagers in response to a
+/// `GetAccountInfo`.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, RustcDecodable, RustcEncodable)]
+pub struct AccountInfo {
+    inbox: BoxUsage,
+    outbox: BoxUsage,
+}
+
+impl AccountInfo {
+    /// Constructor.
+    pub fn new(inbox: BoxUsage, outbox: BoxUsage) -> AccountInfo {
+        AccountInfo {
+            inbox: inbox,
+            outbox: outbox,
+        }
+    }
+
+    /// Getter for the inbox usage.
+    pub fn inbox(&self) -> &BoxUsage {
+        &self.inbox
+    }
+
+    /// Getter for the outbox usage.
+    pub fn outbox(&self) -> &BoxUsage {
+        &self.outbox
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn box_usage() {
+        let usage = BoxUsage::new(900, 3, 1000);
+        assert_eq!(usage.remaining(), 100);
+        assert!(usage.can_accept(100));
+        assert!(!usage.can_accept(101));
+
+        let usage = BoxUsage::new(1200, 4, 1000);
+        assert_eq!(usage.remaining(), 0);
+        assert!(usage.can_accept(0));
+        assert!(!usage.can_accept(1));
+
+        let info = AccountInfo::new(BoxUsage::new(0, 0, ::MAX_INBOX_SIZE as u64), usage);
+        assert_eq!(info.inbox().remaining(), ::MAX_INBOX_SIZE as u64);
+        assert_eq!(*info.outbox(), usage);
+    }
+}
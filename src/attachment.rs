@@ -0,0 +1,162 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::fmt::{self, Debug, Formatter};
+
+use sodiumoxide::crypto::hash::sha512;
+use sodiumoxide::crypto::secretbox;
+use super::Error;
+use super::encryption;
+use xor_name::XorName;
+
+/// A reference to immutable data stored elsewhere on the network, which can be attached to an
+/// [`MpidMessage`](struct.MpidMessage.html) instead of inlining the data in the body.
+#[derive(PartialEq, Eq, Hash, Clone, RustcDecodable, RustcEncodable)]
+pub struct Attachment {
+    name: XorName,
+    size: u64,
+    content_hash: XorName,
+    data_map: Option<Vec<u8>>,
+}
+
+impl Attachment {
+    /// Constructor.
+    ///
+    /// `name` is the network name of the stored data, `size` is its length in bytes and
+    /// `content_hash` is the SHA512 hash of its content.
+    ///
+    /// `data_map` is optional, arbitrary information needed to retrieve or decrypt the data, e.g. a
+    /// serialised data map or decryption key.
+    pub fn new(name: XorName,
+               size: u64,
+               content_hash: XorName,
+               data_map: Option<Vec<u8>>)
+               -> Attachment {
+        Attachment {
+            name: name,
+            size: size,
+            content_hash: content_hash,
+            data_map: data_map,
+        }
+    }
+
+    /// Constructor which calculates the size and hash from the data's `content`.  The other
+    /// arguments are as per [`new()`](#method.new).
+    pub fn from_content(name: XorName, content: &[u8], data_map: Option<Vec<u8>>) -> Attachment {
+        Self::new(name,
+                  content.len() as u64,
+                  XorName(sha512::hash(content).0),
+                  data_map)
+    }
+
+    /// The network name of the stored data.
+    pub fn name(&self) -> &XorName {
+        &self.name
+    }
+
+    /// The length of the stored data in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The SHA512 hash of the stored data.
+    pub fn content_hash(&self) -> &XorName {
+        &self.content_hash
+    }
+
+    /// Optional information needed to retrieve or decrypt the stored data.
+    pub fn data_map(&self) -> Option<&Vec<u8>> {
+        self.data_map.as_ref()
+    }
+
+    /// Returns whether `content` (e.g. the data fetched from the network using
+    /// [`name()`](#method.name)) has the size and hash referenced by this attachment.
+    pub fn verify_content(&self, content: &[u8]) -> bool {
+        content.len() as u64 == self.size && XorName(sha512::hash(content).0) == self.content_hash
+    }
+}
+
+// Used by encrypted messages to encrypt each attachment's data map with the same key as the body.
+// The other fields are left visible.
+pub fn encrypt_data_maps(attachments: Vec<Attachment>, key: &secretbox::Key) -> Vec<Attachment> {
+    attachments.into_iter()
+               .map(|attachment| {
+                   Attachment {
+                       data_map: attachment.data_map
+                                           .map(|data_map| encryption::encrypt(&data_map, key)),
+                       ..attachment
+                   }
+               })
+               .collect()
+}
+
+// Reverses `encrypt_data_maps()`.
+pub fn decrypt_data_maps(attachments: &[Attachment],
+                         key: &secretbox::Key)
+                         -> Result<Vec<Attachment>, Error> {
+    let mut decrypted = Vec::with_capacity(attachments.len());
+    for attachment in attachments {
+        let data_map = match attachment.data_map {
+            Some(ref data_map) => Some(try!(encryption::decrypt(data_map, key))),
+            None => None,
+        };
+        decrypted.push(Attachment { data_map: data_map, ..attachment.clone() });
+    }
+    Ok(decrypted)
+}
+
+impl Debug for Attachment {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter,
+               "Attachment {{ name: {:?}, size: {}, content_hash: {:?}, data_map: {:?} }}",
+               self.name,
+               self.size,
+               self.content_hash,
+               self.data_map.as_ref().map(::format_binary_array))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand;
+    use sodiumoxide::crypto::hash::sha512;
+    use xor_name::XorName;
+
+    #[test]
+    fn verify_content() {
+        let name: XorName = rand::random();
+        let content = ::generate_random_bytes(100);
+        let data_map = ::generate_random_bytes(10);
+
+        let attachment = Attachment::from_content(name.clone(), &content, Some(data_map.clone()));
+        assert_eq!(*attachment.name(), name);
+        assert_eq!(attachment.size(), 100);
+        assert_eq!(attachment.data_map(), Some(&data_map));
+        assert_eq!(attachment,
+                   Attachment::new(name.clone(),
+                                   100,
+                                   XorName(sha512::hash(&content).0),
+                                   Some(data_map)));
+        assert!(attachment.verify_content(&content));
+
+        let mut tampered = content.clone();
+        tampered[0] ^= 1;
+        assert!(!attachment.verify_content(&tampered));
+        assert!(!attachment.verify_content(&content[1..]));
+    }
+}
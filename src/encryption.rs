@@ -0,0 +1,134 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+// Helpers for encrypting message contents so that only the intended recipient can read them.
+
+use sodiumoxide::crypto::{box_, secretbox};
+use super::Error;
+
+// Encrypts `plain_text` so that it can only be decrypted using the secret key corresponding to
+// `public_key`.  A new ephemeral keypair is generated for each call, so the output reveals nothing
+// about the encrypter.  The output is the ephemeral public key, followed by the nonce, followed by
+// the cipher text.
+pub fn seal(plain_text: &[u8], public_key: &box_::PublicKey) -> Vec<u8> {
+    assert!(::initialise_sodiumoxide());
+    let (ephemeral_public_key, ephemeral_secret_key) = box_::gen_keypair();
+    let nonce = box_::gen_nonce();
+    let cipher_text = box_::seal(plain_text, &nonce, public_key, &ephemeral_secret_key);
+    let mut sealed = Vec::with_capacity(box_::PUBLICKEYBYTES + box_::NONCEBYTES +
+                                        cipher_text.len());
+    sealed.extend_from_slice(&ephemeral_public_key.0);
+    sealed.extend_from_slice(&nonce.0);
+    sealed.extend_from_slice(&cipher_text);
+    sealed
+}
+
+// Reverses `seal()`.
+pub fn open(sealed: &[u8], secret_key: &box_::SecretKey) -> Result<Vec<u8>, Error> {
+    if sealed.len() < box_::PUBLICKEYBYTES + box_::NONCEBYTES {
+        return Err(Error::Decryption);
+    }
+    let (public_key_bytes, remainder) = sealed.split_at(box_::PUBLICKEYBYTES);
+    let (nonce_bytes, cipher_text) = remainder.split_at(box_::NONCEBYTES);
+    let ephemeral_public_key = try!(box_::PublicKey::from_slice(public_key_bytes)
+                                        .ok_or(Error::Decryption));
+    let nonce = try!(box_::Nonce::from_slice(nonce_bytes).ok_or(Error::Decryption));
+    box_::open(cipher_text, &nonce, &ephemeral_public_key, secret_key)
+        .map_err(|()| Error::Decryption)
+}
+
+// Encrypts `plain_text` using the symmetric `key`.  The output is the nonce followed by the cipher
+// text.
+pub fn encrypt(plain_text: &[u8], key: &secretbox::Key) -> Vec<u8> {
+    assert!(::initialise_sodiumoxide());
+    let nonce = secretbox::gen_nonce();
+    let cipher_text = secretbox::seal(plain_text, &nonce, key);
+    let mut encrypted = Vec::with_capacity(secretbox::NONCEBYTES + cipher_text.len());
+    encrypted.extend_from_slice(&nonce.0);
+    encrypted.extend_from_slice(&cipher_text);
+    encrypted
+}
+
+// Reverses `encrypt()`.
+pub fn decrypt(encrypted: &[u8], key: &secretbox::Key) -> Result<Vec<u8>, Error> {
+    if encrypted.len() < secretbox::NONCEBYTES {
+        return Err(Error::Decryption);
+    }
+    let (nonce_bytes, cipher_text) = encrypted.split_at(secretbox::NONCEBYTES);
+    let nonce = try!(secretbox::Nonce::from_slice(nonce_bytes).ok_or(Error::Decryption));
+    secretbox::open(cipher_text, &nonce, key).map_err(|()| Error::Decryption)
+}
+
+// Generates a new random symmetric key.
+pub fn generate_key() -> secretbox::Key {
+    assert!(::initialise_sodiumoxide());
+    secretbox::gen_key()
+}
+
+// Generates a new random symmetric key and returns it along with a copy sealed to `public_key`.
+pub fn generate_sealed_key(public_key: &box_::PublicKey) -> (secretbox::Key, Vec<u8>) {
+    let key = generate_key();
+    let sealed_key = seal(&key.0, public_key);
+    (key, sealed_key)
+}
+
+// Reverses the sealing done in `generate_sealed_key()`.
+pub fn open_sealed_key(sealed_key: &[u8],
+                       secret_key: &box_::SecretKey)
+                       -> Result<secretbox::Key, Error> {
+    let key_bytes = try!(open(sealed_key, secret_key));
+    secretbox::Key::from_slice(&key_bytes).ok_or(Error::Decryption)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sodiumoxide::crypto::{box_, secretbox};
+
+    #[test]
+    fn seal_and_open() {
+        let (public_key, secret_key) = box_::gen_keypair();
+        let (_, wrong_secret_key) = box_::gen_keypair();
+        let plain_text = ::generate_random_bytes(100);
+
+        let sealed = seal(&plain_text, &public_key);
+        assert!(sealed != plain_text);
+        assert_eq!(unwrap_result!(open(&sealed, &secret_key)), plain_text);
+        assert!(open(&sealed, &wrong_secret_key).is_err());
+        assert!(open(&sealed[..10], &secret_key).is_err());
+
+        // Sealing the same data twice should yield different outputs.
+        assert!(seal(&plain_text, &public_key) != sealed);
+    }
+
+    #[test]
+    fn encrypt_and_decrypt() {
+        let (public_key, secret_key) = box_::gen_keypair();
+        let (key, sealed_key) = generate_sealed_key(&public_key);
+        let plain_text = ::generate_random_bytes(100);
+
+        let encrypted = encrypt(&plain_text, &key);
+        let opened_key = unwrap_result!(open_sealed_key(&sealed_key, &secret_key));
+        assert_eq!(unwrap_result!(decrypt(&encrypted, &opened_key)), plain_text);
+        assert!(decrypt(&encrypted, &secretbox::gen_key()).is_err());
+
+        let mut tampered = encrypted.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(decrypt(&tampered, &key).is_err());
+    }
+}
@@ -15,6 +15,8 @@
 // Please review the Licences for the specific language governing permissions and limitations
 // relating to use of the SAFE Network Software.
 
+use std::io;
+
 use maidsafe_utilities::serialisation::SerialisationError;
 
 /// Error types relating to MPID messaging.
@@ -26,8 +28,55 @@ pub enum Error {
     /// Used where the length of a [message's `body`](struct.MpidMessage.html#method.new) exceeds
     /// [`MAX_BODY_SIZE`](constant.MAX_BODY_SIZE.html).
     BodyTooLarge,
+    /// Used where a [multi-recipient message](struct.MpidMultiMessage.html#method.new) is given no
+    /// recipients or duplicate recipients.
+    InvalidRecipients,
+    /// Used where a message passed to an
+    /// [`MpidMessageReassembler`](struct.MpidMessageReassembler.html) isn't a valid part, or is
+    /// inconsistent with the other parts of its payload.
+    InvalidPart,
+    /// Used where the same part is passed to an
+    /// [`MpidMessageReassembler`](struct.MpidMessageReassembler.html) more than once.
+    DuplicatePart,
+    /// Used where decryption fails, e.g. because the wrong key was provided or the encrypted data
+    /// has been tampered with.
+    Decryption,
+    /// Used where decryption is requested for a message body or header metadata which was not
+    /// encrypted when constructed.
+    NotEncrypted,
+    /// Used where a [header](struct.MpidHeader.html#method.is_expired) or message has expired.
+    Expired,
+    /// Used where a [header's timestamps](struct.MpidHeader.html#method.validate_time) are
+    /// inconsistent with the current time.
+    InvalidTimestamp,
+    /// Used by MpidManagers where storing a message would exceed
+    /// [`MAX_OUTBOX_SIZE`](constant.MAX_OUTBOX_SIZE.html).
+    OutboxFull,
+    /// Used by MpidManagers where storing a header would exceed
+    /// [`MAX_INBOX_SIZE`](constant.MAX_INBOX_SIZE.html).
+    InboxFull,
+    /// Used where the requested message or header doesn't exist.
+    NotFound,
+    /// Used where a message or header fails signature verification.
+    InvalidSignature,
+    /// Used where the requester isn't permitted to perform the request.
+    Unauthorised,
+    /// Used where an [inbox](struct.Inbox.html#method.put) already holds a different header with
+    /// the same GUID.
+    DuplicateGuid,
+    /// Used where a [signed request](struct.RequestVerifier.html#method.verify) has already been
+    /// handled, or carries an older nonce than one already handled.
+    Replayed,
+    /// Used where a protocol version isn't supported, or where
+    /// [version negotiation](fn.negotiate_version.html) fails.
+    UnsupportedVersion,
+    /// Used where the message in an [envelope](struct.MpidEnvelope.html#method.decode) can't be
+    /// decoded.  The contents is the envelope's request ID.
+    UnknownMessage(u64),
     /// Serialisation error.
     Serialisation(SerialisationError),
+    /// Used where a [mailbox store](trait.MailboxStore.html) fails to read or write its storage.
+    Io(io::Error),
 }
 
 impl From<SerialisationError> for Error {
@@ -35,3 +84,92 @@ impl From<SerialisationError> for Error {
         Error::Serialisation(error)
     }
 }
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+/// The reason carried by a failure response in the
+/// [`MpidMessageWrapper`](enum.MpidMessageWrapper.html) protocol.  These mirror the variants of
+/// [`Error`](enum.Error.html) which can arise when MpidManagers handle a request.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, RustcDecodable, RustcEncodable)]
+pub enum FailureReason {
+    /// See [`Error::MetadataTooLarge`](enum.Error.html#variant.MetadataTooLarge).
+    MetadataTooLarge,
+    /// See [`Error::BodyTooLarge`](enum.Error.html#variant.BodyTooLarge).
+    BodyTooLarge,
+    /// See [`Error::InvalidRecipients`](enum.Error.html#variant.InvalidRecipients).
+    InvalidRecipients,
+    /// See [`Error::Expired`](enum.Error.html#variant.Expired).
+    Expired,
+    /// See [`Error::InvalidTimestamp`](enum.Error.html#variant.InvalidTimestamp).
+    InvalidTimestamp,
+    /// See [`Error::OutboxFull`](enum.Error.html#variant.OutboxFull).
+    OutboxFull,
+    /// See [`Error::InboxFull`](enum.Error.html#variant.InboxFull).
+    InboxFull,
+    /// See [`Error::NotFound`](enum.Error.html#variant.NotFound).
+    NotFound,
+    /// See [`Error::InvalidSignature`](enum.Error.html#variant.InvalidSignature).
+    InvalidSignature,
+    /// See [`Error::Unauthorised`](enum.Error.html#variant.Unauthorised).
+    Unauthorised,
+    /// See [`Error::UnsupportedVersion`](enum.Error.html#variant.UnsupportedVersion).
+    UnsupportedVersion,
+    /// See [`Error::UnknownMessage`](enum.Error.html#variant.UnknownMessage).
+    UnknownMessage,
+    /// Used for any other error, e.g. where the request couldn't be parsed.
+    InvalidRequest,
+    /// See [`Error::Replayed`](enum.Error.html#variant.Replayed).
+    Replayed,
+    /// See [`Error::DuplicateGuid`](enum.Error.html#variant.DuplicateGuid).
+    DuplicateGuid,
+    /// See [`Error::Io`](enum.Error.html#variant.Io).
+    StorageFailure,
+    /// Used where MpidManagers give up waiting for another account's MpidManagers, e.g. to confirm
+    /// a `Recall`.
+    TimedOut,
+}
+
+impl FailureReason {
+    /// Returns the oldest protocol version able to represent this reason, i.e. the version which
+    /// added it.
+    pub fn min_version(&self) -> u32 {
+        match *self {
+            FailureReason::Replayed => 2,
+            FailureReason::DuplicateGuid |
+            FailureReason::StorageFailure => 7,
+            FailureReason::TimedOut => 9,
+            _ => 1,
+        }
+    }
+}
+
+impl<'a> From<&'a Error> for FailureReason {
+    fn from(error: &'a Error) -> FailureReason {
+        match *error {
+            Error::MetadataTooLarge => FailureReason::MetadataTooLarge,
+            Error::BodyTooLarge => FailureReason::BodyTooLarge,
+            Error::InvalidRecipients => FailureReason::InvalidRecipients,
+            Error::Expired => FailureReason::Expired,
+            Error::InvalidTimestamp => FailureReason::InvalidTimestamp,
+            Error::OutboxFull => FailureReason::OutboxFull,
+            Error::InboxFull => FailureReason::InboxFull,
+            Error::NotFound => FailureReason::NotFound,
+            Error::InvalidSignature => FailureReason::InvalidSignature,
+            Error::Unauthorised => FailureReason::Unauthorised,
+            Error::DuplicateGuid => FailureReason::DuplicateGuid,
+            Error::Replayed => FailureReason::Replayed,
+            Error::UnsupportedVersion => FailureReason::UnsupportedVersion,
+            Error::UnknownMessage(_) => FailureReason::UnknownMessage,
+            Error::Io(_) => FailureReason::StorageFailure,
+            Error::InvalidPart |
+            Error::DuplicatePart |
+            Error::Decryption |
+            Error::NotEncrypted |
+            Error::Serialisation(_) => FailureReason::InvalidRequest,
+        }
+    }
+}
@@ -0,0 +1,200 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+/// The maximum number of headers returned in a single [`HeaderPage`](struct.HeaderPage.html).
+/// Larger limits requested via a [`PageRequest`](struct.PageRequest.html) are reduced to this.
+pub const MAX_HEADERS_PER_PAGE: u32 = 100;
+
+use std::cmp;
+
+use maidsafe_utilities::serialisation::{deserialise, serialise};
+use super::{Error, MpidHeader};
+use xor_name::XorName;
+
+/// Requests a single page of headers when listing the contents of an inbox or outbox.
+#[derive(PartialEq, Eq, Hash, Clone, Debug, RustcDecodable, RustcEncodable)]
+pub struct PageRequest {
+    cursor: Option<Vec<u8>>,
+    limit: u32,
+}
+
+impl PageRequest {
+    /// Requests the first page, holding at most `limit` headers.
+    pub fn first(limit: u32) -> PageRequest {
+        PageRequest {
+            cursor: None,
+            limit: limit,
+        }
+    }
+
+    /// Requests the page following `page`, holding at most the same number of headers as this
+    /// request.  Returns `None` if `page` was the last one.
+    pub fn next(&self, page: &HeaderPage) -> Option<PageRequest> {
+        page.next_cursor.as_ref().map(|cursor| {
+            PageRequest {
+                cursor: Some(cursor.clone()),
+                limit: self.limit,
+            }
+        })
+    }
+
+    /// The opaque cursor identifying where the page starts, or `None` for the first page.
+    pub fn cursor(&self) -> Option<&Vec<u8>> {
+        self.cursor.as_ref()
+    }
+
+    /// The maximum number of headers requested.
+    pub fn limit(&self) -> u32 {
+        self.limit
+    }
+}
+
+/// A single page of headers, sent in response to a [`PageRequest`](struct.PageRequest.html).
+#[derive(PartialEq, Eq, Hash, Clone, Debug, RustcDecodable, RustcEncodable)]
+pub struct HeaderPage {
+    headers: Vec<MpidHeader>,
+    next_cursor: Option<Vec<u8>>,
+}
+
+impl HeaderPage {
+    /// Used by MpidManagers to select the page of `headers` identified by `request`.
+    ///
+    /// Headers are listed in order of their names, so the order of `headers` is irrelevant and
+    /// headers added or removed between requests don't cause others to be skipped or repeated.  At
+    /// most `request.limit()` headers (bounded by
+    /// [`MAX_HEADERS_PER_PAGE`](constant.MAX_HEADERS_PER_PAGE.html)) are included.  An error is
+    /// returned if the request's cursor is malformed.
+    pub fn new(headers: &[MpidHeader], request: &PageRequest) -> Result<HeaderPage, Error> {
+        let mut named = Vec::with_capacity(headers.len());
+        for header in headers {
+            named.push((try!(header.name()), header));
+        }
+        named.sort_by(|lhs, rhs| lhs.0.cmp(&rhs.0));
+        select(named.iter().map(|&(ref name, header)| (name, header)), request)
+    }
+
+    /// The headers in this page.
+    pub fn headers(&self) -> &Vec<MpidHeader> {
+        &self.headers
+    }
+
+    /// Consumes the page, returning its headers.
+    pub fn into_headers(self) -> Vec<MpidHeader> {
+        self.headers
+    }
+
+    /// The cursor for retrieving the following page via
+    /// [`PageRequest::next()`](struct.PageRequest.html#method.next), or `None` if this is the last
+    /// page.
+    pub fn next_cursor(&self) -> Option<&Vec<u8>> {
+        self.next_cursor.as_ref()
+    }
+
+    /// Whether this is the last page.
+    pub fn is_last(&self) -> bool {
+        self.next_cursor.is_none()
+    }
+}
+
+/// As per [`HeaderPage::new()`](struct.HeaderPage.html#method.new), but for headers already
+/// paired with their names and sorted by name, e.g. by iterating a `BTreeMap` keyed by name.
+pub fn select<'a, I>(named_headers: I, request: &PageRequest) -> Result<HeaderPage, Error>
+    where I: Iterator<Item = (&'a XorName, &'a MpidHeader)>
+{
+    let start = match request.cursor {
+        Some(ref cursor) => Some(try!(deserialise::<XorName>(cursor))),
+        None => None,
+    };
+    let limit = match request.limit {
+        0 => MAX_HEADERS_PER_PAGE,
+        limit => cmp::min(limit, MAX_HEADERS_PER_PAGE),
+    } as usize;
+    let mut selected = named_headers.filter(|&(name, _)| {
+                                        start.as_ref().map_or(true, |start| name > start)
+                                    })
+                                    .take(limit + 1)
+                                    .collect::<Vec<_>>();
+    let next_cursor = if selected.len() > limit {
+        selected.truncate(limit);
+        Some(try!(serialise(selected[limit - 1].0)))
+    } else {
+        None
+    };
+    Ok(HeaderPage {
+        headers: selected.into_iter().map(|(_, header)| header.clone()).collect(),
+        next_cursor: next_cursor,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand;
+    use sodiumoxide::crypto::sign;
+    use xor_name::XorName;
+    use MpidHeader;
+
+    #[test]
+    fn full() {
+        let (_, secret_key) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let mut headers = Vec::new();
+        for _ in 0..(MAX_HEADERS_PER_PAGE + 10) {
+            let recipient: XorName = rand::random();
+            headers.push(unwrap_result!(MpidHeader::new(sender.clone(),
+                                                        vec![],
+                                                        recipient,
+                                                        &[],
+                                                        None,
+                                                        &secret_key)));
+        }
+
+        // Page through in small steps, checking every header is listed exactly once.
+        let mut request = PageRequest::first(7);
+        let mut listed = Vec::new();
+        loop {
+            let page = unwrap_result!(HeaderPage::new(&headers, &request));
+            assert!(page.headers().len() <= 7);
+            listed.extend(page.headers().iter().cloned());
+            match request.next(&page) {
+                Some(next) => request = next,
+                None => break,
+            }
+        }
+        assert_eq!(listed.len(), headers.len());
+        for header in &headers {
+            assert!(listed.contains(header));
+        }
+
+        // Oversized and zero limits are both bounded.
+        let page = unwrap_result!(HeaderPage::new(&headers, &PageRequest::first(u32::max_value())));
+        assert_eq!(page.headers().len(), MAX_HEADERS_PER_PAGE as usize);
+        assert!(!page.is_last());
+        let page = unwrap_result!(HeaderPage::new(&headers, &PageRequest::first(0)));
+        assert_eq!(page.headers().len(), MAX_HEADERS_PER_PAGE as usize);
+
+        // Empty listing, and malformed cursor.
+        let page = unwrap_result!(HeaderPage::new(&[], &PageRequest::first(10)));
+        assert!(page.headers().is_empty());
+        assert!(page.is_last());
+        let request = PageRequest {
+            cursor: Some(vec![1, 2, 3]),
+            limit: 10,
+        };
+        assert!(HeaderPage::new(&headers, &request).is_err());
+    }
+}
@@ -0,0 +1,260 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::collections::{BTreeMap, HashMap, btree_map};
+
+use maidsafe_utilities::serialisation::serialise;
+use super::{BoxUsage, Error, HeaderPage, MpidHeader, PageRequest, GUID_SIZE, MAX_INBOX_SIZE};
+use super::header_page;
+use xor_name::XorName;
+
+struct Entry {
+    header: MpidHeader,
+    size: u64,
+    sequence: u64,
+}
+
+/// Used by MpidManagers to hold the headers of messages sent to a single Client, keyed by header
+/// name, until the Client deletes them.
+///
+/// Each header is charged its serialised size against the inbox's limit.  Headers are iterated in
+/// the order in which they arrived.
+pub struct Inbox {
+    headers: BTreeMap<XorName, Entry>,
+    arrival_order: BTreeMap<u64, XorName>,
+    guids: HashMap<[u8; GUID_SIZE], XorName>,
+    next_sequence: u64,
+    bytes_used: u64,
+    limit: u64,
+}
+
+impl Inbox {
+    /// Constructs an empty inbox limited to [`MAX_INBOX_SIZE`](constant.MAX_INBOX_SIZE.html).
+    pub fn new() -> Inbox {
+        Inbox::with_limit(MAX_INBOX_SIZE as u64)
+    }
+
+    /// Constructs an empty inbox limited to `limit` bytes.
+    pub fn with_limit(limit: u64) -> Inbox {
+        Inbox {
+            headers: BTreeMap::new(),
+            arrival_order: BTreeMap::new(),
+            guids: HashMap::new(),
+            next_sequence: 0,
+            bytes_used: 0,
+            limit: limit,
+        }
+    }
+
+    /// Stores `header`, returning its name.  Storing a header which is already held has no effect.
+    ///
+    /// `Error::DuplicateGuid` is returned if a different header with the same GUID is held, and
+    /// `Error::InboxFull` if the header would take the inbox over its limit.
+    pub fn put(&mut self, header: MpidHeader) -> Result<XorName, Error> {
+        let name = try!(header.name());
+        if let Some(existing) = self.guids.get(header.guid()) {
+            return if *existing == name {
+                Ok(name)
+            } else {
+                Err(Error::DuplicateGuid)
+            };
+        }
+        let size = try!(header_size(&header));
+        if !self.usage().can_accept(size) {
+            return Err(Error::InboxFull);
+        }
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.bytes_used += size;
+        let _ = self.guids.insert(*header.guid(), name.clone());
+        let _ = self.arrival_order.insert(sequence, name.clone());
+        let _ = self.headers.insert(name.clone(),
+                                    Entry {
+                                        header: header,
+                                        size: size,
+                                        sequence: sequence,
+                                    });
+        Ok(name)
+    }
+
+    /// Returns the header with the given name.
+    pub fn get(&self, name: &XorName) -> Option<&MpidHeader> {
+        self.headers.get(name).map(|entry| &entry.header)
+    }
+
+    /// Removes and returns the header with the given name, e.g. on a `DeleteMessage` from the
+    /// Client or a `RecallHeader` from the sender's MpidManagers.
+    pub fn remove(&mut self, name: &XorName) -> Option<MpidHeader> {
+        self.headers.remove(name).map(|entry| {
+            let _ = self.arrival_order.remove(&entry.sequence);
+            let _ = self.guids.remove(entry.header.guid());
+            self.bytes_used -= entry.size;
+            entry.header
+        })
+    }
+
+    /// Answers an `InboxHas`, returning those named headers which are held.
+    pub fn has(&self, names: &[XorName]) -> Vec<MpidHeader> {
+        names.iter()
+             .filter_map(|name| self.headers.get(name))
+             .map(|entry| entry.header.clone())
+             .collect()
+    }
+
+    /// Answers a `GetInboxHeaders`, as per
+    /// [`HeaderPage::new()`](struct.HeaderPage.html#method.new).
+    pub fn headers(&self, request: &PageRequest) -> Result<HeaderPage, Error> {
+        header_page::select(self.headers.iter().map(|(name, entry)| (name, &entry.header)),
+                            request)
+    }
+
+    /// Iterates the headers in the order in which they arrived.
+    pub fn iter(&self) -> InboxIter {
+        InboxIter {
+            names: self.arrival_order.values(),
+            headers: &self.headers,
+        }
+    }
+
+    /// The usage of the inbox, e.g. for answering a `GetAccountInfo`.
+    pub fn usage(&self) -> BoxUsage {
+        BoxUsage::new(self.bytes_used, self.headers.len() as u64, self.limit)
+    }
+
+    /// The number of headers held.
+    pub fn len(&self) -> usize {
+        self.headers.len()
+    }
+
+    /// Whether the inbox is empty.
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+}
+
+impl Default for Inbox {
+    fn default() -> Inbox {
+        Inbox::new()
+    }
+}
+
+/// Iterator over the headers in an [`Inbox`](struct.Inbox.html), in the order in which they
+/// arrived.
+pub struct InboxIter<'a> {
+    names: btree_map::Values<'a, u64, XorName>,
+    headers: &'a BTreeMap<XorName, Entry>,
+}
+
+impl<'a> Iterator for InboxIter<'a> {
+    type Item = &'a MpidHeader;
+
+    fn next(&mut self) -> Option<&'a MpidHeader> {
+        let headers = self.headers;
+        self.names.next().and_then(|name| headers.get(name)).map(|entry| &entry.header)
+    }
+}
+
+/// The size charged against the inbox's limit for `header`, i.e. its serialised size.
+pub fn header_size(header: &MpidHeader) -> Result<u64, Error> {
+    Ok(try!(serialise(header)).len() as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use maidsafe_utilities::serialisation::{deserialise, serialise};
+    use rand;
+    use sodiumoxide::crypto::sign;
+    use xor_name::XorName;
+    use {Error, MpidHeader, PageRequest};
+
+    #[test]
+    fn full() {
+        let (_, secret_key) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let recipient: XorName = rand::random();
+        let new_header = || {
+            unwrap_result!(MpidHeader::new(sender.clone(),
+                                           vec![],
+                                           recipient.clone(),
+                                           &[],
+                                           None,
+                                           &secret_key))
+        };
+        let header1 = new_header();
+        let header2 = new_header();
+        let header3 = new_header();
+        let size = unwrap_result!(header_size(&header1));
+
+        // Fill an inbox with room for exactly three headers.
+        let mut inbox = Inbox::with_limit(3 * size);
+        let name1 = unwrap_result!(inbox.put(header1.clone()));
+        let name2 = unwrap_result!(inbox.put(header2.clone()));
+        let _ = unwrap_result!(inbox.put(header3.clone()));
+        assert_eq!(unwrap_result!(inbox.put(header2.clone())), name2);
+        assert_eq!(inbox.len(), 3);
+        match inbox.put(new_header()) {
+            Err(Error::InboxFull) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        // Iteration follows arrival order, and removal frees space.
+        assert_eq!(inbox.iter().cloned().collect::<Vec<_>>(),
+                   vec![header1.clone(), header2.clone(), header3.clone()]);
+        assert_eq!(inbox.remove(&name2), Some(header2.clone()));
+        assert!(inbox.remove(&name2).is_none());
+        assert_eq!(inbox.usage().bytes_used(), 2 * size);
+        let header4 = new_header();
+        let _ = unwrap_result!(inbox.put(header4.clone()));
+        assert_eq!(inbox.iter().cloned().collect::<Vec<_>>(),
+                   vec![header1.clone(), header3.clone(), header4.clone()]);
+
+        // Queries.
+        assert_eq!(inbox.has(&[name1.clone(), name2]), vec![header1.clone()]);
+        assert_eq!(inbox.get(&name1), Some(&header1));
+        let page = unwrap_result!(inbox.headers(&PageRequest::first(2)));
+        assert_eq!(page.headers().len(), 2);
+        assert!(!page.is_last());
+    }
+
+    #[test]
+    fn duplicate_guid() {
+        let (_, secret_key) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let recipient: XorName = rand::random();
+        let header = unwrap_result!(MpidHeader::new(sender,
+                                                    vec![],
+                                                    recipient,
+                                                    &[],
+                                                    None,
+                                                    &secret_key));
+        let mut inbox = Inbox::new();
+        let _ = unwrap_result!(inbox.put(header.clone()));
+
+        // Altering the signature, which is serialised last, yields a different header with the
+        // same GUID.
+        let mut serialised = unwrap_result!(serialise(&header));
+        let last = unwrap_option!(serialised.last_mut(), "");
+        *last = last.wrapping_add(1);
+        let forged: MpidHeader = unwrap_result!(deserialise(&serialised));
+        assert_eq!(forged.guid(), header.guid());
+        match inbox.put(forged) {
+            Err(Error::DuplicateGuid) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+}
@@ -57,17 +57,84 @@ pub const MAX_INBOX_SIZE: usize = 1 << 27;
 /// Maximum allowed outbox size for an account (128 MiB).
 pub const MAX_OUTBOX_SIZE: usize = 1 << 27;
 
+mod account_info;
+mod attachment;
+mod encryption;
 mod error;
+mod header_page;
+mod inbox;
+mod mailbox;
+mod mailbox_store;
+mod mpid_envelope;
 mod mpid_header;
+mod mpid_manager;
 mod mpid_message;
+mod mpid_message_reassembler;
 mod mpid_message_wrapper;
+mod mpid_multi_message;
+mod mpid_receipt;
+mod outbox;
+mod presence_tracker;
+mod request_tracker;
+mod signed_request;
 
-pub use error::Error;
-pub use mpid_header::{MpidHeader, MAX_HEADER_METADATA_SIZE};
+pub use account_info::{AccountInfo, BoxUsage};
+pub use attachment::Attachment;
+pub use error::{Error, FailureReason};
+pub use header_page::{HeaderPage, PageRequest, MAX_HEADERS_PER_PAGE};
+pub use inbox::{Inbox, InboxIter};
+pub use mailbox::{Mailbox, MailboxEvent};
+pub use mailbox_store::{AppendLogStore, MailboxStore, MemoryStore, StoredAccount};
+pub use mpid_envelope::{MpidEnvelope, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, negotiate_version};
+pub use mpid_header::{MpidHeader, MAX_CLOCK_SKEW, MAX_HEADER_METADATA_SIZE};
+pub use mpid_manager::{Action, Authority, MpidManager};
 pub use mpid_message::{MpidMessage, MAX_BODY_SIZE};
-pub use mpid_message_wrapper::MpidMessageWrapper;
+pub use mpid_message_reassembler::{MpidMessageReassembler, MAX_PAYLOAD_SIZE,
+                                   split_encrypted_payload, split_payload};
+pub use mpid_message_wrapper::{MpidMessageWrapper, RecallOutcome};
+pub use mpid_multi_message::MpidMultiMessage;
+pub use mpid_receipt::{MpidReceipt, ReceiptKind};
+pub use outbox::Outbox;
+pub use presence_tracker::PresenceTracker;
+pub use request_tracker::RequestTracker;
+pub use signed_request::{RequestVerifier, SignedRequest};
 
 use std::fmt::Write;
+use std::sync::{Once, ONCE_INIT};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sodiumoxide::crypto::hash::sha512;
+use sodiumoxide::crypto::sign::PublicKey;
+use xor_name::XorName;
+
+static INITIALISE_SODIUMOXIDE: Once = ONCE_INIT;
+static mut sodiumoxide_init_result: bool = false;
+
+#[allow(unsafe_code)]
+fn initialise_sodiumoxide() -> bool {
+    unsafe {
+        INITIALISE_SODIUMOXIDE.call_once(|| {
+            sodiumoxide_init_result = sodiumoxide::init();
+        });
+        sodiumoxide_init_result
+    }
+}
+
+/// Derives the name of an account from its public signing key.
+///
+/// A sender's name is expected to be the SHA512 hash of its public signing key, which allows the
+/// name in a [header](struct.MpidHeader.html#method.verify_sender) or
+/// [message](struct.MpidMessage.html#method.verify_sender) to be checked against the key which
+/// signed it.
+pub fn name_from_public_key(public_key: &PublicKey) -> XorName {
+    XorName(sha512::hash(&public_key.0).0)
+}
+
+// The current time in seconds since the UNIX epoch.  If the system clock is set before the epoch,
+// this returns 0.
+fn seconds_since_epoch() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0)
+}
 
 // Format a vector of bytes as a hexadecimal number, ellipsising all but the first and last three.
 //
@@ -0,0 +1,452 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::cmp;
+use std::collections::HashMap;
+
+use sodiumoxide::crypto::sign::{PublicKey, SecretKey};
+use super::{Error, FailureReason, MpidEnvelope, MpidHeader, MpidMessage, MpidMessageWrapper,
+            MpidReceipt, PageRequest, ReceiptKind, RecallOutcome, RequestTracker, SignedRequest,
+            MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use xor_name::XorName;
+
+/// An event resulting from a message received by a [`Mailbox`](struct.Mailbox.html), or from
+/// requests timing out.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum MailboxEvent {
+    /// The MpidManagers acknowledged a `VersionedOnline`.  The contents is the negotiated protocol
+    /// version.
+    Connected(u32),
+    /// A header of a new message was received.
+    NewHeader(MpidHeader),
+    /// A message requested via [`get_message()`](struct.Mailbox.html#method.get_message) was
+    /// retrieved, and has been verified against the requested header and the sender's key.
+    MessageRetrieved(MpidMessage),
+    /// The recipient of a message sent by this Client acknowledged it.  The receipt should be
+    /// checked via [`MpidReceipt::verify_for()`](struct.MpidReceipt.html#method.verify_for).
+    MessageAcknowledged(MpidReceipt),
+    /// A message sent by this Client was rejected, either by its MpidManagers or by the
+    /// recipient's.  The contents is the name of the message's header and the reason.
+    MessageRejected(XorName, FailureReason),
+    /// A message sent by this Client was recalled.
+    Recalled(XorName, RecallOutcome),
+    /// A response other than those above was received.  The contents is the request and the
+    /// response.
+    Response(MpidMessageWrapper, MpidMessageWrapper),
+    /// A request failed.  The contents is the request and the reason.
+    RequestFailed(MpidMessageWrapper, FailureReason),
+    /// A request for which the MpidManagers only respond on failure, such as a `PutMessage` or
+    /// `DeleteMessage`, completed without a failure being reported within the timeout.
+    RequestCompleted(MpidMessageWrapper),
+    /// A request received no response within the timeout.
+    RequestTimedOut(MpidMessageWrapper),
+}
+
+/// A network-agnostic implementation of the Client's side of the protocol.
+///
+/// Each method which issues a request returns the name of the account whose MpidManagers it should
+/// be sent to, along with the signed envelope.  The Client passes every envelope received from its
+/// MpidManagers to [`handle()`](#method.handle), and periodically calls
+/// [`expire()`](#method.expire), then reacts to the resulting events.  All times are in seconds
+/// since the UNIX epoch.
+pub struct Mailbox {
+    name: XorName,
+    public_key: PublicKey,
+    secret_key: SecretKey,
+    last_nonce: u64,
+    version: Option<u32>,
+    tracker: RequestTracker,
+    // The sender's public key for each pending `GetMessage`, keyed by request ID.
+    sender_keys: HashMap<u64, PublicKey>,
+}
+
+impl Mailbox {
+    /// Constructor.  `public_key` and `secret_key` are the Client's signing keys, and requests are
+    /// deemed to have timed out if no response arrives within `request_timeout` seconds.
+    pub fn new(public_key: PublicKey, secret_key: SecretKey, request_timeout: u64) -> Mailbox {
+        Mailbox {
+            name: ::name_from_public_key(&public_key),
+            public_key: public_key,
+            secret_key: secret_key,
+            last_nonce: 0,
+            version: None,
+            tracker: RequestTracker::new(request_timeout),
+            sender_keys: HashMap::new(),
+        }
+    }
+
+    /// The Client's name, as per [`name_from_public_key()`](fn.name_from_public_key.html).
+    pub fn name(&self) -> &XorName {
+        &self.name
+    }
+
+    /// The protocol version negotiated with the MpidManagers, or `None` if not connected.
+    pub fn version(&self) -> Option<u32> {
+        self.version
+    }
+
+    /// Issues a `VersionedOnline`.
+    pub fn online(&mut self, now: u64) -> Result<(XorName, MpidEnvelope), Error> {
+        let name = self.name.clone();
+        self.send(name,
+                  MpidMessageWrapper::VersionedOnline(MIN_PROTOCOL_VERSION, PROTOCOL_VERSION),
+                  now)
+    }
+
+    /// Issues an `Offline`.
+    pub fn offline(&mut self, now: u64) -> Result<(XorName, MpidEnvelope), Error> {
+        self.version = None;
+        let name = self.name.clone();
+        self.send(name, MpidMessageWrapper::Offline, now)
+    }
+
+    /// Issues a `KeepAlive`.
+    pub fn keep_alive(&mut self, now: u64) -> Result<(XorName, MpidEnvelope), Error> {
+        let name = self.name.clone();
+        self.send(name, MpidMessageWrapper::KeepAlive, now)
+    }
+
+    /// Issues a `PutMessage` to store `message` in the Client's outbox.
+    pub fn put_message(&mut self,
+                       message: MpidMessage,
+                       now: u64)
+                       -> Result<(XorName, MpidEnvelope), Error> {
+        let name = self.name.clone();
+        self.send(name, MpidMessageWrapper::PutMessage(message), now)
+    }
+
+    /// Issues a `GetMessage` to retrieve the message corresponding to `header`.
+    ///
+    /// `sender_public_key` is the signing key of the message's sender, as named by the header or,
+    /// for a sealed sender, as revealed by
+    /// [MpidHeader::open_sender()](struct.MpidHeader.html#method.open_sender).  The retrieved
+    /// message is only reported if it has the requested header and is validly signed with this key;
+    /// otherwise the request fails with `InvalidSignature`.
+    pub fn get_message(&mut self,
+                       header: MpidHeader,
+                       sender_public_key: PublicKey,
+                       now: u64)
+                       -> Result<(XorName, MpidEnvelope), Error> {
+        let name = self.name.clone();
+        let (dst, envelope) = try!(self.send(name, MpidMessageWrapper::GetMessage(header), now));
+        let _ = self.sender_keys.insert(envelope.request_id(), sender_public_key);
+        Ok((dst, envelope))
+    }
+
+    /// Issues a `GetInboxHeaders` for the page of headers identified by `request`.
+    pub fn get_inbox_headers(&mut self,
+                             request: PageRequest,
+                             now: u64)
+                             -> Result<(XorName, MpidEnvelope), Error> {
+        let name = self.name.clone();
+        self.send(name, MpidMessageWrapper::GetInboxHeaders(request), now)
+    }
+
+    /// Issues a `DeleteMessage` to delete the named message from the Client's inbox or outbox.
+    pub fn delete_message(&mut self,
+                          name: XorName,
+                          now: u64)
+                          -> Result<(XorName, MpidEnvelope), Error> {
+        let own_name = self.name.clone();
+        self.send(own_name, MpidMessageWrapper::DeleteMessage(name), now)
+    }
+
+    /// Issues a `DeleteHeader` to the MpidManagers of `sender` to delete the named message from
+    /// its outbox.
+    pub fn delete_header(&mut self,
+                         sender: XorName,
+                         name: XorName,
+                         now: u64)
+                         -> Result<(XorName, MpidEnvelope), Error> {
+        self.send(sender, MpidMessageWrapper::DeleteHeader(name), now)
+    }
+
+    /// Issues a `PutReceipt` to the MpidManagers of `sender` acknowledging the message
+    /// corresponding to `header`.
+    pub fn acknowledge(&mut self,
+                       sender: XorName,
+                       header: &MpidHeader,
+                       kind: ReceiptKind,
+                       now: u64)
+                       -> Result<(XorName, MpidEnvelope), Error> {
+        let receipt = try!(MpidReceipt::new(kind, header, &self.secret_key));
+        self.send(sender, MpidMessageWrapper::PutReceipt(receipt), now)
+    }
+
+    /// Issues a `Recall` of the named message.
+    pub fn recall(&mut self, name: XorName, now: u64) -> Result<(XorName, MpidEnvelope), Error> {
+        let own_name = self.name.clone();
+        self.send(own_name, MpidMessageWrapper::Recall(name), now)
+    }
+
+    /// Handles an envelope received from MpidManagers, returning the resulting events.
+    pub fn handle(&mut self, envelope: MpidEnvelope) -> Vec<MailboxEvent> {
+        let sender_public_key = self.sender_keys.remove(&envelope.request_id());
+        let (request, response) = match self.tracker.receive(envelope) {
+            Ok((request, response)) => (unsigned(request), response),
+            Err(envelope) => return unsolicited(envelope.into_message()).into_iter().collect(),
+        };
+        let event = match response {
+            MpidMessageWrapper::OnlineResponse(version) => {
+                self.version = Some(version);
+                MailboxEvent::Connected(version)
+            }
+            MpidMessageWrapper::PutMessage(message) => {
+                let is_valid = match (&request, sender_public_key) {
+                    (&MpidMessageWrapper::GetMessage(ref header), Some(ref public_key)) => {
+                        is_requested_message(&message, header, public_key)
+                    }
+                    _ => false,
+                };
+                if is_valid {
+                    MailboxEvent::MessageRetrieved(message)
+                } else {
+                    MailboxEvent::RequestFailed(request, FailureReason::InvalidSignature)
+                }
+            }
+            MpidMessageWrapper::PutMessageFailure(name, reason) => {
+                MailboxEvent::MessageRejected(name, reason)
+            }
+            MpidMessageWrapper::RecallResponse(name, outcome) => {
+                MailboxEvent::Recalled(name, outcome)
+            }
+            response => {
+                match response.failure_reason() {
+                    Some(reason) => MailboxEvent::RequestFailed(request, reason),
+                    None => MailboxEvent::Response(request, response),
+                }
+            }
+        };
+        vec![event]
+    }
+
+    /// Reports every request which has timed out by `now`.
+    pub fn expire(&mut self, now: u64) -> Vec<MailboxEvent> {
+        self.tracker
+            .expire(now)
+            .into_iter()
+            .map(|(request_id, request)| {
+                let _ = self.sender_keys.remove(&request_id);
+                let request = unsigned(request);
+                if is_silent_on_success(&request) {
+                    MailboxEvent::RequestCompleted(request)
+                } else {
+                    MailboxEvent::RequestTimedOut(request)
+                }
+            })
+            .collect()
+    }
+
+    // Signs `request` with a nonce which increases across restarts as long as the clock does, and
+    // records it as pending.
+    fn send(&mut self,
+            dst: XorName,
+            request: MpidMessageWrapper,
+            now: u64)
+            -> Result<(XorName, MpidEnvelope), Error> {
+        let nonce = cmp::max(self.last_nonce + 1, now.saturating_mul(1_000_000));
+        let signed = try!(SignedRequest::new(request, nonce, &self.public_key, &self.secret_key));
+        self.last_nonce = nonce;
+        Ok((dst, self.tracker.send(MpidMessageWrapper::Signed(signed), now)))
+    }
+}
+
+// Messages pushed by MpidManagers other than in response to a pending request.
+fn unsolicited(message: MpidMessageWrapper) -> Option<MailboxEvent> {
+    match message {
+        MpidMessageWrapper::PutHeader(header) => Some(MailboxEvent::NewHeader(header)),
+        MpidMessageWrapper::PutReceipt(receipt) => Some(MailboxEvent::MessageAcknowledged(receipt)),
+        MpidMessageWrapper::PutMessageFailure(name, reason) => {
+            Some(MailboxEvent::MessageRejected(name, reason))
+        }
+        _ => None,
+    }
+}
+
+// Whether `message` is the one `header` refers to, signed by the holder of `public_key`.  Unless
+// the sender is sealed, it must also be the sender named in the header.
+fn is_requested_message(message: &MpidMessage,
+                        header: &MpidHeader,
+                        public_key: &PublicKey)
+                        -> bool {
+    if message.header() != header {
+        return false;
+    }
+    if header.is_sender_sealed() {
+        message.verify(public_key)
+    } else {
+        message.verify_sender(public_key)
+    }
+}
+
+fn unsigned(request: MpidMessageWrapper) -> MpidMessageWrapper {
+    match request {
+        MpidMessageWrapper::Signed(signed) => signed.into_request(),
+        request => request,
+    }
+}
+
+fn is_silent_on_success(request: &MpidMessageWrapper) -> bool {
+    match *request {
+        MpidMessageWrapper::Offline |
+        MpidMessageWrapper::KeepAlive |
+        MpidMessageWrapper::PutMessage(_) |
+        MpidMessageWrapper::PutMultiMessage(_) |
+        MpidMessageWrapper::DeleteMessage(_) |
+        MpidMessageWrapper::DeleteHeader(_) |
+        MpidMessageWrapper::PutReceipt(_) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sodiumoxide::crypto::sign;
+    use xor_name::XorName;
+    use {Action, Authority, FailureReason, MpidEnvelope, MpidManager, MpidMessage,
+         MpidMessageWrapper, ReceiptKind, PROTOCOL_VERSION};
+
+    fn new_mailbox() -> Mailbox {
+        let (public_key, secret_key) = sign::gen_keypair();
+        Mailbox::new(public_key, secret_key, 30)
+    }
+
+    // Passes a request from `client` to the MpidManagers, routes the resulting messages between
+    // MpidManagers and Clients, and returns the events raised by each of `mailboxes`.
+    fn send(manager: &mut MpidManager,
+            client: XorName,
+            request: (XorName, MpidEnvelope),
+            mailboxes: &mut [&mut Mailbox])
+            -> Vec<Vec<MailboxEvent>> {
+        let mut events = mailboxes.iter().map(|_| Vec::new()).collect::<Vec<_>>();
+        let now = ::seconds_since_epoch();
+        let mut pending = manager.handle(Authority::Client(client), request.0, request.1, now);
+        while !pending.is_empty() {
+            let Action::Send { src, dst, envelope, version } = pending.remove(0);
+            let encoded = unwrap_result!(envelope.encode(version));
+            let envelope = unwrap_result!(MpidEnvelope::decode(&encoded));
+            match dst {
+                Authority::Client(name) => {
+                    let index = unwrap_option!(mailboxes.iter().position(|mailbox| {
+                                                   *mailbox.name() == name
+                                               }),
+                                               "");
+                    events[index].extend(mailboxes[index].handle(envelope));
+                }
+                Authority::Managers(name) => {
+                    pending.extend(manager.handle(Authority::Managers(src), name, envelope, now))
+                }
+            }
+        }
+        events
+    }
+
+    #[test]
+    fn full() {
+        let mut manager = MpidManager::new(60);
+        let mut alice = new_mailbox();
+        let mut bob = new_mailbox();
+        let now = ::seconds_since_epoch();
+
+        // Both come online.
+        let request = unwrap_result!(alice.online(now));
+        let events = send(&mut manager, alice.name().clone(), request, &mut [&mut alice]);
+        assert_eq!(events[0], vec![MailboxEvent::Connected(PROTOCOL_VERSION)]);
+        assert_eq!(alice.version(), Some(PROTOCOL_VERSION));
+        let request = unwrap_result!(bob.online(now));
+        let _ = send(&mut manager, bob.name().clone(), request, &mut [&mut bob]);
+
+        // Alice sends a message; Bob is notified of its header.
+        let message = unwrap_result!(MpidMessage::new(alice.name().clone(),
+                                                      vec![],
+                                                      bob.name().clone(),
+                                                      vec![1, 2, 3],
+                                                      vec![],
+                                                      None,
+                                                      &alice.secret_key));
+        let header = message.header().clone();
+        let header_name = unwrap_result!(header.name());
+        let request = unwrap_result!(alice.put_message(message.clone(), now));
+        let events = send(&mut manager, alice.name().clone(), request, &mut [&mut alice, &mut bob]);
+        assert!(events[0].is_empty());
+        assert_eq!(events[1], vec![MailboxEvent::NewHeader(header.clone())]);
+
+        // Bob can only retrieve the message given Alice's key, then acknowledges it.
+        let bob_public_key = bob.public_key;
+        let request = unwrap_result!(bob.get_message(header.clone(), bob_public_key, now));
+        let events = send(&mut manager, bob.name().clone(), request, &mut [&mut bob]);
+        assert_eq!(events[0],
+                   vec![MailboxEvent::RequestFailed(MpidMessageWrapper::GetMessage(header.clone()),
+                                                    FailureReason::InvalidSignature)]);
+        let request = unwrap_result!(bob.get_message(header.clone(), alice.public_key, now));
+        let events = send(&mut manager, bob.name().clone(), request, &mut [&mut bob]);
+        assert_eq!(events[0], vec![MailboxEvent::MessageRetrieved(message.clone())]);
+
+        // A validly-signed message other than the one requested is rejected.
+        let other_message = unwrap_result!(MpidMessage::new(alice.name().clone(),
+                                                            vec![],
+                                                            bob.name().clone(),
+                                                            vec![4, 5, 6],
+                                                            vec![],
+                                                            None,
+                                                            &alice.secret_key));
+        let (_, request) = unwrap_result!(bob.get_message(header.clone(), alice.public_key, now));
+        let response = MpidEnvelope::new(request.request_id(),
+                                         MpidMessageWrapper::PutMessage(other_message));
+        assert_eq!(bob.handle(response),
+                   vec![MailboxEvent::RequestFailed(MpidMessageWrapper::GetMessage(header.clone()),
+                                                    FailureReason::InvalidSignature)]);
+        let request = unwrap_result!(bob.acknowledge(alice.name().clone(),
+                                                     &header,
+                                                     ReceiptKind::Read,
+                                                     now));
+        let events = send(&mut manager, bob.name().clone(), request, &mut [&mut alice, &mut bob]);
+        let receipt = match events[0].get(0) {
+            Some(&MailboxEvent::MessageAcknowledged(ref receipt)) => receipt.clone(),
+            event => panic!("Unexpected event: {:?}", event),
+        };
+        assert!(receipt.verify_for(&header, &bob.public_key));
+
+        // Bob deletes the header from Alice's outbox; a second attempt fails.
+        let request = unwrap_result!(bob.delete_header(alice.name().clone(),
+                                                       header_name.clone(),
+                                                       now));
+        let events = send(&mut manager, bob.name().clone(), request, &mut [&mut bob]);
+        assert!(events[0].is_empty());
+        let request = unwrap_result!(bob.delete_header(alice.name().clone(),
+                                                       header_name.clone(),
+                                                       now));
+        let events = send(&mut manager, bob.name().clone(), request, &mut [&mut bob]);
+        let delete_header = MpidMessageWrapper::DeleteHeader(header_name);
+        assert_eq!(events[0],
+                   vec![MailboxEvent::RequestFailed(delete_header.clone(),
+                                                    FailureReason::NotFound)]);
+
+        // The receipt and the successful delete complete silently once their timeout passes.
+        assert!(bob.expire(now + 29).is_empty());
+        let events = bob.expire(now + 30);
+        assert_eq!(events.len(), 2);
+        assert!(events.contains(&MailboxEvent::RequestCompleted(delete_header)));
+        assert!(events.iter().all(|event| {
+            match *event {
+                MailboxEvent::RequestCompleted(_) => true,
+                _ => false,
+            }
+        }));
+    }
+}
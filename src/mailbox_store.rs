@@ -0,0 +1,824 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use maidsafe_utilities::serialisation::{deserialise, serialise};
+use sodiumoxide::crypto::hash::sha512;
+use super::{Error, MpidHeader, MpidMessage, MpidMultiMessage};
+use xor_name::XorName;
+
+// Each record in an append log is framed by its length (4 bytes, little-endian) and a checksum (the
+// first 8 bytes of its SHA-512 hash).
+const FRAME_HEADER_SIZE: usize = 12;
+
+// The size beyond which an append log is compacted by default (64 MiB).
+const DEFAULT_COMPACTION_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+/// Persistent storage for the inboxes and outboxes held by an
+/// [`MpidManager`](struct.MpidManager.html).
+///
+/// The MpidManager writes every change through to its store, and on construction via
+/// [`MpidManager::with_store()`](struct.MpidManager.html#method.with_store) rebuilds its
+/// mailboxes, including their size accounting, from [`load()`](#tymethod.load).  Each method
+/// takes the name of the account affected and the name of the message or header.
+///
+/// The store also holds the last nonce accepted from each Client, so that the MpidManager's
+/// [`RequestVerifier`](struct.RequestVerifier.html) keeps rejecting replayed requests after a
+/// restart.
+pub trait MailboxStore {
+    /// Records that `message` has been added to the outbox of `account`.
+    fn put_message(&mut self,
+                   account: &XorName,
+                   name: &XorName,
+                   message: &MpidMessage)
+                   -> Result<(), Error>;
+
+    /// Records that the multi-recipient `message` has been added to the outbox of `account`, with
+    /// `names` being the names of its recipients' headers.
+    fn put_multi_message(&mut self,
+                         account: &XorName,
+                         names: &[XorName],
+                         message: &MpidMultiMessage)
+                         -> Result<(), Error>;
+
+    /// Records that the named message has been retrieved by its recipient.
+    fn mark_retrieved(&mut self, account: &XorName, name: &XorName) -> Result<(), Error>;
+
+    /// Records that the named message has been removed from the outbox of `account`.  For a
+    /// recipient of a multi-recipient message, only that recipient is removed.
+    fn remove_message(&mut self, account: &XorName, name: &XorName) -> Result<(), Error>;
+
+    /// Records that `header` has been added to the inbox of `account`, having been delivered by
+    /// the MpidManagers of the account `sender`.
+    fn put_header(&mut self,
+                  account: &XorName,
+                  name: &XorName,
+                  header: &MpidHeader,
+                  sender: &XorName)
+                  -> Result<(), Error>;
+
+    /// Records that the named header has been removed from the inbox of `account`.
+    fn remove_header(&mut self, account: &XorName, name: &XorName) -> Result<(), Error>;
+
+    /// Records that a signed request with `nonce` has been accepted from `requester`.
+    fn put_nonce(&mut self, requester: &XorName, nonce: u64) -> Result<(), Error>;
+
+    /// Returns the current contents of every non-empty account.
+    fn load(&self) -> Result<HashMap<XorName, StoredAccount>, Error>;
+
+    /// Returns the greatest nonce recorded for each Client.
+    fn load_nonces(&self) -> Result<HashMap<XorName, u64>, Error>;
+}
+
+/// The stored contents of a single account, as returned by
+/// [`MailboxStore::load()`](trait.MailboxStore.html#tymethod.load).
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct StoredAccount {
+    messages: BTreeMap<XorName, MpidMessage>,
+    // Keyed by the first of the header names with which each was stored.
+    multi_messages: BTreeMap<XorName, (MpidMultiMessage, Vec<XorName>)>,
+    retrieved: BTreeSet<XorName>,
+    headers: Vec<(XorName, MpidHeader, XorName)>,
+}
+
+impl StoredAccount {
+    /// The single-recipient messages in the outbox.
+    pub fn messages(&self) -> Vec<&MpidMessage> {
+        self.messages.values().collect()
+    }
+
+    /// The multi-recipient messages in the outbox, holding only the recipients which haven't been
+    /// removed.
+    pub fn multi_messages(&self) -> Vec<&MpidMultiMessage> {
+        self.multi_messages.values().map(|&(ref message, _)| message).collect()
+    }
+
+    /// Whether the named message has been retrieved by its recipient.
+    pub fn is_retrieved(&self, name: &XorName) -> bool {
+        self.retrieved.contains(name)
+    }
+
+    /// The headers in the inbox, in the order in which they arrived, each with the name of the
+    /// account whose MpidManagers delivered it.
+    pub fn headers(&self) -> Vec<(&MpidHeader, &XorName)> {
+        self.headers.iter().map(|&(_, ref header, ref sender)| (header, sender)).collect()
+    }
+
+    fn has_message(&self, name: &XorName) -> bool {
+        self.messages.contains_key(name) ||
+        self.multi_messages.values().any(|&(_, ref names)| names.contains(name))
+    }
+
+    fn remove_message(&mut self, name: &XorName) {
+        let _ = self.retrieved.remove(name);
+        if self.messages.remove(name).is_some() {
+            return;
+        }
+        let key = match self.multi_messages
+                            .iter()
+                            .find(|&(_, &(_, ref names))| names.contains(name)) {
+            Some((key, _)) => key.clone(),
+            None => return,
+        };
+        // Entries are keyed by their first remaining name, as they are when replayed from records.
+        if let Some((mut message, mut names)) = self.multi_messages.remove(&key) {
+            let _ = message.remove_recipient(name);
+            names.retain(|existing| existing != name);
+            if let Some(key) = names.first().cloned() {
+                let _ = self.multi_messages.insert(key, (message, names));
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.messages.is_empty() && self.multi_messages.is_empty() && self.headers.is_empty()
+    }
+}
+
+/// A [`MailboxStore`](trait.MailboxStore.html) held only in memory.
+///
+/// This holds a copy of everything written to it, so is mainly of use for testing; an
+/// [`MpidManager`](struct.MpidManager.html) constructed via
+/// [`new()`](struct.MpidManager.html#method.new) records nothing.
+#[derive(Default)]
+pub struct MemoryStore {
+    accounts: HashMap<XorName, StoredAccount>,
+    nonces: HashMap<XorName, u64>,
+}
+
+impl MemoryStore {
+    /// Constructs an empty store.
+    pub fn new() -> MemoryStore {
+        MemoryStore {
+            accounts: HashMap::new(),
+            nonces: HashMap::new(),
+        }
+    }
+
+    fn account(&mut self, account: &XorName) -> &mut StoredAccount {
+        self.accounts.entry(account.clone()).or_insert_with(StoredAccount::default)
+    }
+
+    fn remove_if_empty(&mut self, account: &XorName) {
+        if self.accounts.get(account).map_or(false, StoredAccount::is_empty) {
+            let _ = self.accounts.remove(account);
+        }
+    }
+
+    fn apply(&mut self, record: Record) {
+        match record {
+            Record::PutMessage(account, name, message) => {
+                let _ = self.account(&account).messages.insert(name, message);
+            }
+            Record::MarkRetrieved(account, name) => {
+                let stored = self.account(&account);
+                if stored.has_message(&name) {
+                    let _ = stored.retrieved.insert(name);
+                }
+            }
+            Record::RemoveMessage(account, name) => {
+                self.account(&account).remove_message(&name);
+                self.remove_if_empty(&account);
+            }
+            Record::PutHeader(account, name, header, sender) => {
+                let headers = &mut self.account(&account).headers;
+                if !headers.iter().any(|&(ref existing, _, _)| *existing == name) {
+                    headers.push((name, header, sender));
+                }
+            }
+            Record::RemoveHeader(account, name) => {
+                self.account(&account).headers.retain(|&(ref existing, _, _)| *existing != name);
+                self.remove_if_empty(&account);
+            }
+            Record::PutMultiMessage(account, names, message) => {
+                if let Some(key) = names.first().cloned() {
+                    let _ = self.account(&account).multi_messages.insert(key, (message, names));
+                }
+            }
+            Record::PutNonce(requester, nonce) => {
+                let last_nonce = self.nonces.entry(requester).or_insert(nonce);
+                if *last_nonce < nonce {
+                    *last_nonce = nonce;
+                }
+            }
+        }
+    }
+
+    // The records which recreate the current contents.
+    fn records(&self) -> Vec<Record> {
+        let mut records = Vec::new();
+        for (account, stored) in &self.accounts {
+            for (name, message) in &stored.messages {
+                records.push(Record::PutMessage(account.clone(), name.clone(), message.clone()));
+            }
+            for &(ref message, ref names) in stored.multi_messages.values() {
+                records.push(Record::PutMultiMessage(account.clone(),
+                                                     names.clone(),
+                                                     message.clone()));
+            }
+            for name in &stored.retrieved {
+                records.push(Record::MarkRetrieved(account.clone(), name.clone()));
+            }
+            for &(ref name, ref header, ref sender) in &stored.headers {
+                records.push(Record::PutHeader(account.clone(),
+                                               name.clone(),
+                                               header.clone(),
+                                               sender.clone()));
+            }
+        }
+        for (requester, nonce) in &self.nonces {
+            records.push(Record::PutNonce(requester.clone(), *nonce));
+        }
+        records
+    }
+}
+
+impl MailboxStore for MemoryStore {
+    fn put_message(&mut self,
+                   account: &XorName,
+                   name: &XorName,
+                   message: &MpidMessage)
+                   -> Result<(), Error> {
+        self.apply(Record::PutMessage(account.clone(), name.clone(), message.clone()));
+        Ok(())
+    }
+
+    fn put_multi_message(&mut self,
+                         account: &XorName,
+                         names: &[XorName],
+                         message: &MpidMultiMessage)
+                         -> Result<(), Error> {
+        self.apply(Record::PutMultiMessage(account.clone(), names.to_vec(), message.clone()));
+        Ok(())
+    }
+
+    fn mark_retrieved(&mut self, account: &XorName, name: &XorName) -> Result<(), Error> {
+        self.apply(Record::MarkRetrieved(account.clone(), name.clone()));
+        Ok(())
+    }
+
+    fn remove_message(&mut self, account: &XorName, name: &XorName) -> Result<(), Error> {
+        self.apply(Record::RemoveMessage(account.clone(), name.clone()));
+        Ok(())
+    }
+
+    fn put_header(&mut self,
+                  account: &XorName,
+                  name: &XorName,
+                  header: &MpidHeader,
+                  sender: &XorName)
+                  -> Result<(), Error> {
+        self.apply(Record::PutHeader(account.clone(),
+                                     name.clone(),
+                                     header.clone(),
+                                     sender.clone()));
+        Ok(())
+    }
+
+    fn remove_header(&mut self, account: &XorName, name: &XorName) -> Result<(), Error> {
+        self.apply(Record::RemoveHeader(account.clone(), name.clone()));
+        Ok(())
+    }
+
+    fn put_nonce(&mut self, requester: &XorName, nonce: u64) -> Result<(), Error> {
+        self.apply(Record::PutNonce(requester.clone(), nonce));
+        Ok(())
+    }
+
+    fn load(&self) -> Result<HashMap<XorName, StoredAccount>, Error> {
+        Ok(self.accounts.clone())
+    }
+
+    fn load_nonces(&self) -> Result<HashMap<XorName, u64>, Error> {
+        Ok(self.nonces.clone())
+    }
+}
+
+// A `MailboxStore` which records nothing, used by an `MpidManager` whose mailboxes are held only in
+// memory, since it already holds their contents.
+pub struct NullStore;
+
+impl MailboxStore for NullStore {
+    fn put_message(&mut self, _: &XorName, _: &XorName, _: &MpidMessage) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn put_multi_message(&mut self,
+                         _: &XorName,
+                         _: &[XorName],
+                         _: &MpidMultiMessage)
+                         -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn mark_retrieved(&mut self, _: &XorName, _: &XorName) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn remove_message(&mut self, _: &XorName, _: &XorName) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn put_header(&mut self,
+                  _: &XorName,
+                  _: &XorName,
+                  _: &MpidHeader,
+                  _: &XorName)
+                  -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn remove_header(&mut self, _: &XorName, _: &XorName) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn put_nonce(&mut self, _: &XorName, _: u64) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn load(&self) -> Result<HashMap<XorName, StoredAccount>, Error> {
+        Ok(HashMap::new())
+    }
+
+    fn load_nonces(&self) -> Result<HashMap<XorName, u64>, Error> {
+        Ok(HashMap::new())
+    }
+}
+
+// New variants must be appended, so that existing logs remain readable.
+#[derive(RustcDecodable, RustcEncodable)]
+enum Record {
+    PutMessage(XorName, XorName, MpidMessage),
+    MarkRetrieved(XorName, XorName),
+    RemoveMessage(XorName, XorName),
+    PutHeader(XorName, XorName, MpidHeader, XorName),
+    RemoveHeader(XorName, XorName),
+    PutMultiMessage(XorName, Vec<XorName>, MpidMultiMessage),
+    PutNonce(XorName, u64),
+}
+
+/// A [`MailboxStore`](trait.MailboxStore.html) persisted to a single file as a log of changes.
+///
+/// Each change is appended and synced to disk before the corresponding method returns.  If a crash
+/// leaves an incomplete record at the end of the log, [`open()`](#method.open) discards it, so the
+/// store recovers to the state following the last complete change.  Any other damage to the log is
+/// reported as an error rather than discarding the records affected.
+///
+/// The contents are not held in memory, since the MpidManager already holds them;
+/// [`load()`](trait.MailboxStore.html#tymethod.load) replays the log from disk.  Once the log has
+/// grown beyond its compaction threshold and to twice its size when last compacted, it is
+/// [compacted](#method.compact) automatically.
+pub struct AppendLogStore {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    compacted_size: u64,
+    compaction_threshold: u64,
+}
+
+impl AppendLogStore {
+    /// Opens the log at `path`, creating it if it doesn't exist, and checks its contents.  The log
+    /// is compacted automatically once it exceeds 64 MiB, as per
+    /// [`open_with_threshold()`](#method.open_with_threshold).
+    ///
+    /// An error is returned, leaving the log untouched, if a record other than the last fails its
+    /// checksum, or if any record passes its checksum but can't be decoded.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<AppendLogStore, Error> {
+        Self::open_with_threshold(path, DEFAULT_COMPACTION_THRESHOLD)
+    }
+
+    /// As per [`open()`](#method.open), but compacting the log automatically once it exceeds
+    /// `compaction_threshold` bytes, and has at least doubled in size since it was last compacted.
+    pub fn open_with_threshold<P: AsRef<Path>>(path: P,
+                                               compaction_threshold: u64)
+                                               -> Result<AppendLogStore, Error> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = try!(OpenOptions::new().read(true).append(true).create(true).open(&path));
+        let mut contents = Vec::new();
+        let _ = try!(file.read_to_end(&mut contents));
+        let (_, length) = try!(replay(&contents));
+        if length < contents.len() {
+            try!(file.set_len(length as u64));
+            try!(file.sync_all());
+        }
+        Ok(AppendLogStore {
+            path: path,
+            file: file,
+            size: length as u64,
+            compacted_size: length as u64,
+            compaction_threshold: compaction_threshold,
+        })
+    }
+
+    /// Rewrites the log to hold only the current contents, discarding the history of changes.
+    ///
+    /// The new log is written alongside the existing one and then renamed over it, so a crash
+    /// during compaction leaves one or other intact.
+    pub fn compact(&mut self) -> Result<(), Error> {
+        let state = try!(self.read());
+        let mut temp_path = self.path.clone().into_os_string();
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+        let mut size = 0;
+        let file = {
+            let mut temp_file = try!(File::create(&temp_path));
+            for record in state.records() {
+                let framed = try!(frame(&record));
+                try!(temp_file.write_all(&framed));
+                size += framed.len() as u64;
+            }
+            try!(temp_file.sync_all());
+            // Opened before the rename, so that if this fails the existing log remains in use.
+            try!(OpenOptions::new().read(true).append(true).open(&temp_path))
+        };
+        try!(fs::rename(&temp_path, &self.path));
+        self.file = file;
+        self.size = size;
+        self.compacted_size = size;
+        Ok(())
+    }
+
+    /// The current size of the log in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn append(&mut self, record: Record) -> Result<(), Error> {
+        let framed = try!(frame(&record));
+        let result = self.file.write_all(&framed).and_then(|()| self.file.sync_data());
+        if let Err(error) = result {
+            // Remove any partial record, so that later ones aren't appended after it.
+            let _ = self.file.set_len(self.size);
+            return Err(Error::Io(error));
+        }
+        self.size += framed.len() as u64;
+        if self.size > self.compaction_threshold &&
+           self.size >= self.compacted_size.saturating_mul(2) {
+            // The change has already been recorded, and a failed compaction leaves the log intact,
+            // so it is retried after the next change rather than reported.
+            let _ = self.compact();
+        }
+        Ok(())
+    }
+
+    fn read(&self) -> Result<MemoryStore, Error> {
+        let mut contents = Vec::new();
+        let _ = try!(try!(File::open(&self.path)).read_to_end(&mut contents));
+        Ok(try!(replay(&contents)).0)
+    }
+}
+
+impl MailboxStore for AppendLogStore {
+    fn put_message(&mut self,
+                   account: &XorName,
+                   name: &XorName,
+                   message: &MpidMessage)
+                   -> Result<(), Error> {
+        self.append(Record::PutMessage(account.clone(), name.clone(), message.clone()))
+    }
+
+    fn put_multi_message(&mut self,
+                         account: &XorName,
+                         names: &[XorName],
+                         message: &MpidMultiMessage)
+                         -> Result<(), Error> {
+        self.append(Record::PutMultiMessage(account.clone(), names.to_vec(), message.clone()))
+    }
+
+    fn mark_retrieved(&mut self, account: &XorName, name: &XorName) -> Result<(), Error> {
+        self.append(Record::MarkRetrieved(account.clone(), name.clone()))
+    }
+
+    fn remove_message(&mut self, account: &XorName, name: &XorName) -> Result<(), Error> {
+        self.append(Record::RemoveMessage(account.clone(), name.clone()))
+    }
+
+    fn put_header(&mut self,
+                  account: &XorName,
+                  name: &XorName,
+                  header: &MpidHeader,
+                  sender: &XorName)
+                  -> Result<(), Error> {
+        self.append(Record::PutHeader(account.clone(),
+                                      name.clone(),
+                                      header.clone(),
+                                      sender.clone()))
+    }
+
+    fn remove_header(&mut self, account: &XorName, name: &XorName) -> Result<(), Error> {
+        self.append(Record::RemoveHeader(account.clone(), name.clone()))
+    }
+
+    fn put_nonce(&mut self, requester: &XorName, nonce: u64) -> Result<(), Error> {
+        self.append(Record::PutNonce(requester.clone(), nonce))
+    }
+
+    fn load(&self) -> Result<HashMap<XorName, StoredAccount>, Error> {
+        Ok(try!(self.read()).accounts)
+    }
+
+    fn load_nonces(&self) -> Result<HashMap<XorName, u64>, Error> {
+        Ok(try!(self.read()).nonces)
+    }
+}
+
+fn checksum(payload: &[u8]) -> [u8; 8] {
+    let hash = sha512::hash(payload);
+    let mut checksum = [0; 8];
+    checksum.copy_from_slice(&hash.0[..8]);
+    checksum
+}
+
+fn frame(record: &Record) -> Result<Vec<u8>, Error> {
+    let payload = try!(serialise(record));
+    let length = payload.len() as u32;
+    let mut framed = Vec::with_capacity(FRAME_HEADER_SIZE + payload.len());
+    framed.extend_from_slice(&[length as u8,
+                               (length >> 8) as u8,
+                               (length >> 16) as u8,
+                               (length >> 24) as u8]);
+    framed.extend_from_slice(&checksum(&payload));
+    framed.extend_from_slice(&payload);
+    Ok(framed)
+}
+
+// Replays the records in `contents`, returning the resulting contents and the length of `contents`
+// up to the end of the last complete record.
+fn replay(contents: &[u8]) -> Result<(MemoryStore, usize), Error> {
+    let mut state = MemoryStore::new();
+    let mut offset = 0;
+    while let Some((record, length)) = try!(parse_frame(&contents[offset..])) {
+        state.apply(record);
+        offset += length;
+    }
+    Ok((state, offset))
+}
+
+// Parses the record at the start of `input`, returning it and the length of its frame, or `None` if
+// `input` holds only a torn write, i.e. an incomplete frame or a final frame failing its checksum.
+fn parse_frame(input: &[u8]) -> Result<Option<(Record, usize)>, Error> {
+    if input.len() < FRAME_HEADER_SIZE {
+        return Ok(None);
+    }
+    let length = input[0] as usize | (input[1] as usize) << 8 | (input[2] as usize) << 16 |
+                 (input[3] as usize) << 24;
+    let end = FRAME_HEADER_SIZE + length;
+    if input.len() < end {
+        return Ok(None);
+    }
+    let payload = &input[FRAME_HEADER_SIZE..end];
+    if checksum(payload) != input[4..FRAME_HEADER_SIZE] {
+        if input.len() == end {
+            return Ok(None);
+        }
+        return Err(Error::Io(io::Error::new(ErrorKind::InvalidData,
+                                            "corrupt record in mailbox log")));
+    }
+    Ok(Some((try!(deserialise(payload)), end)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand;
+    use sodiumoxide::crypto::sign;
+    use std::env;
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{Read, Write};
+    use std::path::PathBuf;
+    use xor_name::XorName;
+    use {MpidMessage, MpidMultiMessage};
+
+    fn new_message() -> (XorName, MpidMessage) {
+        let (_, secret_key) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let recipient: XorName = rand::random();
+        let message = unwrap_result!(MpidMessage::new(sender,
+                                                      vec![],
+                                                      recipient,
+                                                      vec![1, 2, 3],
+                                                      vec![],
+                                                      None,
+                                                      &secret_key));
+        (unwrap_result!(message.name()), message)
+    }
+
+    fn temp_path() -> PathBuf {
+        env::temp_dir().join(format!("mpid_messaging_{:016x}.log", rand::random::<u64>()))
+    }
+
+    // Adds two messages, a message for three recipients and two headers to `store`, then removes
+    // one of each, including one recipient.
+    fn populate(store: &mut MailboxStore,
+                account: &XorName)
+                -> (MpidMessage, MpidMultiMessage, MpidMessage) {
+        let (name1, message1) = new_message();
+        let (name2, message2) = new_message();
+        let (header_name1, message3) = new_message();
+        let (header_name2, message4) = new_message();
+        unwrap_result!(store.put_message(account, &name1, &message1));
+        unwrap_result!(store.put_message(account, &name2, &message2));
+        unwrap_result!(store.mark_retrieved(account, &name2));
+        unwrap_result!(store.remove_message(account, &name1));
+        for &(ref name, ref message) in &[(header_name1.clone(), message3),
+                                          (header_name2, message4.clone())] {
+            let sender = unwrap_option!(message.header().sender(), "");
+            unwrap_result!(store.put_header(account, name, message.header(), sender));
+        }
+        unwrap_result!(store.remove_header(account, &header_name1));
+
+        let (_, secret_key) = sign::gen_keypair();
+        let recipients = (0..3).map(|_| rand::random()).collect();
+        let mut multi_message = unwrap_result!(MpidMultiMessage::new(rand::random(),
+                                                                     vec![],
+                                                                     recipients,
+                                                                     vec![1, 2, 3],
+                                                                     vec![],
+                                                                     None,
+                                                                     &secret_key));
+        let names = multi_message.headers()
+                                 .iter()
+                                 .map(|header| unwrap_result!(header.name()))
+                                 .collect::<Vec<_>>();
+        unwrap_result!(store.put_multi_message(account, &names, &multi_message));
+        unwrap_result!(store.mark_retrieved(account, &names[1]));
+        unwrap_result!(store.remove_message(account, &names[0]));
+        assert!(multi_message.remove_recipient(&names[0]));
+
+        unwrap_result!(store.put_nonce(account, 2));
+        unwrap_result!(store.put_nonce(account, 1));
+        (message2, multi_message, message4)
+    }
+
+    #[test]
+    fn memory_store() {
+        let mut store = MemoryStore::new();
+        let account: XorName = rand::random();
+        let (message, multi_message, header_message) = populate(&mut store, &account);
+
+        let loaded = unwrap_result!(store.load());
+        let stored = unwrap_option!(loaded.get(&account), "");
+        assert_eq!(stored.messages(), vec![&message]);
+        assert!(stored.is_retrieved(&unwrap_result!(message.name())));
+        assert_eq!(stored.multi_messages(), vec![&multi_message]);
+        let names = multi_message.headers()
+                                 .iter()
+                                 .map(|header| unwrap_result!(header.name()))
+                                 .collect::<Vec<_>>();
+        assert!(stored.is_retrieved(&names[0]));
+        assert!(!stored.is_retrieved(&names[1]));
+        let header = header_message.header();
+        assert_eq!(stored.headers(),
+                   vec![(header, unwrap_option!(header.sender(), ""))]);
+        let nonces = unwrap_result!(store.load_nonces());
+        assert_eq!(nonces.len(), 1);
+        assert_eq!(nonces.get(&account), Some(&2));
+
+        // Removing the remaining recipients removes the multi-recipient message.
+        for name in &names {
+            unwrap_result!(store.remove_message(&account, name));
+        }
+        let loaded = unwrap_result!(store.load());
+        assert!(unwrap_option!(loaded.get(&account), "").multi_messages().is_empty());
+    }
+
+    #[test]
+    fn append_log_store() {
+        let path = temp_path();
+        let account: XorName = rand::random();
+        let expected = {
+            let mut store = unwrap_result!(AppendLogStore::open(&path));
+            let _ = populate(&mut store, &account);
+            unwrap_result!(store.load())
+        };
+
+        // Reopening recovers the same contents.
+        {
+            let store = unwrap_result!(AppendLogStore::open(&path));
+            assert_eq!(unwrap_result!(store.load()), expected);
+        }
+
+        // A torn write at the end of the log is discarded.
+        let length = unwrap_result!(fs::metadata(&path)).len();
+        {
+            let mut file = unwrap_result!(OpenOptions::new().append(true).open(&path));
+            unwrap_result!(file.write_all(&[200, 0, 0, 0, 1, 2, 3]));
+        }
+        {
+            let mut store = unwrap_result!(AppendLogStore::open(&path));
+            assert_eq!(unwrap_result!(store.load()), expected);
+            assert_eq!(unwrap_result!(fs::metadata(&path)).len(), length);
+
+            // Further changes are appended after the recovered records, and compaction preserves
+            // the contents while shrinking the log.
+            let (name, message) = new_message();
+            unwrap_result!(store.put_message(&account, &name, &message));
+            unwrap_result!(store.remove_message(&account, &name));
+            unwrap_result!(store.compact());
+            assert!(unwrap_result!(fs::metadata(&path)).len() < length);
+        }
+        {
+            let store = unwrap_result!(AppendLogStore::open(&path));
+            assert_eq!(unwrap_result!(store.load()), expected);
+            assert_eq!(unwrap_result!(store.load_nonces()).get(&account), Some(&2));
+        }
+        unwrap_result!(fs::remove_file(&path));
+    }
+
+    #[test]
+    fn automatic_compaction() {
+        let path = temp_path();
+        let account: XorName = rand::random();
+        let (name, message) = new_message();
+        {
+            // Without compaction, the log would grow by two records per iteration.
+            let mut store = unwrap_result!(AppendLogStore::open_with_threshold(&path, 1000));
+            for _ in 0..100 {
+                unwrap_result!(store.put_message(&account, &name, &message));
+                unwrap_result!(store.remove_message(&account, &name));
+            }
+            assert!(store.size() < 2000);
+            assert_eq!(unwrap_result!(fs::metadata(&path)).len(), store.size());
+            assert!(unwrap_result!(store.load()).is_empty());
+            unwrap_result!(store.put_message(&account, &name, &message));
+        }
+
+        // Changes made after compaction are written to the compacted log.
+        {
+            let store = unwrap_result!(AppendLogStore::open(&path));
+            assert_eq!(unwrap_result!(store.load()).len(), 1);
+        }
+        unwrap_result!(fs::remove_file(&path));
+    }
+
+    #[test]
+    fn damaged_log() {
+        let path = temp_path();
+        let account: XorName = rand::random();
+        {
+            let mut store = unwrap_result!(AppendLogStore::open(&path));
+            let _ = populate(&mut store, &account);
+        }
+        let mut contents = Vec::new();
+        {
+            let mut file = unwrap_result!(File::open(&path));
+            let _ = unwrap_result!(file.read_to_end(&mut contents));
+        }
+        let write = |contents: &[u8]| {
+            let mut file = unwrap_result!(File::create(&path));
+            unwrap_result!(file.write_all(contents));
+        };
+        let assert_open_fails = |contents: &[u8]| {
+            write(contents);
+            assert!(AppendLogStore::open(&path).is_err());
+            assert_eq!(unwrap_result!(fs::metadata(&path)).len(), contents.len() as u64);
+        };
+
+        // A frame with a valid checksum which can't be decoded isn't discarded as a torn write,
+        // whether or not it is the last in the log.
+        let payload = [255; 16];
+        let mut undecodable = vec![payload.len() as u8, 0, 0, 0];
+        undecodable.extend_from_slice(&checksum(&payload));
+        undecodable.extend_from_slice(&payload);
+        let mut damaged = contents.clone();
+        damaged.extend_from_slice(&undecodable);
+        assert_open_fails(&damaged);
+        let mut damaged = undecodable.clone();
+        damaged.extend_from_slice(&contents);
+        assert_open_fails(&damaged);
+
+        // Nor is a frame failing its checksum which is followed by further records.
+        let mut damaged = contents.clone();
+        damaged[FRAME_HEADER_SIZE] ^= 1;
+        assert_open_fails(&damaged);
+
+        // Only the final frame may fail its checksum, in which case it is discarded.
+        let mut torn = contents.clone();
+        let last = torn.len() - 1;
+        torn[last] ^= 1;
+        write(&torn);
+        {
+            let _store = unwrap_result!(AppendLogStore::open(&path));
+            assert!(unwrap_result!(fs::metadata(&path)).len() < contents.len() as u64);
+        }
+        unwrap_result!(fs::remove_file(&path));
+    }
+}
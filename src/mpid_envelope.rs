@@ -0,0 +1,272 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+/// The current version of the [`MpidMessageWrapper`](enum.MpidMessageWrapper.html) protocol.
+///
+/// This must be incremented whenever variants are appended to `MpidMessageWrapper` or
+/// [`FailureReason`](enum.FailureReason.html), so that the negotiated version tells each side
+/// which variants the other understands:
+///
+/// 1. The initial version, up to and including `OnlineResponse`.  The variants up to and including
+///    `DeleteHeader` predate versioning.
+/// 2. Adds `Signed` and `FailureReason::Replayed`.
+/// 3. Adds `Recall`, `RecallHeader`, `RecallResponse` and `RecallFailure`.
+/// 4. Adds `Offline` and `KeepAlive`.
+/// 5. Adds `GetAccountInfo` and `GetAccountInfoResponse`.
+/// 6. Adds `PutMessages` to `DeleteHeadersResponse`.
+/// 7. Adds `FailureReason::DuplicateGuid` and `FailureReason::StorageFailure`.
+/// 8. Adds `RecallHeaderResponse`.
+/// 9. Adds `FailureReason::TimedOut`.
+pub const PROTOCOL_VERSION: u32 = 9;
+/// The oldest protocol version which can still be encoded and decoded.
+///
+/// This must be incremented whenever a change means peers using older versions can no longer be
+/// served, e.g. version 2 made MpidManagers reject requests which aren't wrapped in a `Signed`.
+pub const MIN_PROTOCOL_VERSION: u32 = 2;
+
+use maidsafe_utilities::serialisation::{deserialise, serialise};
+use super::{Error, MpidMessageWrapper};
+
+// The wire format of an `MpidEnvelope`.  Its layout must never change, so that a peer can always
+// read the version and request ID even where it can't decode the message itself.
+#[derive(RustcDecodable, RustcEncodable)]
+struct Packet {
+    version: u32,
+    request_id: u64,
+    message: Vec<u8>,
+}
+
+/// Returns the highest protocol version supported both locally and by a peer supporting versions
+/// `min_version` to `max_version` inclusive, or `None` if there is no such version.
+///
+/// A Client sends its supported range in a `VersionedOnline`, and its MpidManagers reply with the
+/// negotiated version in an `OnlineResponse`.  Both sides then
+/// [encode](struct.MpidEnvelope.html#method.encode) using that version.
+pub fn negotiate_version(min_version: u32, max_version: u32) -> Option<u32> {
+    let version = if max_version < PROTOCOL_VERSION {
+        max_version
+    } else {
+        PROTOCOL_VERSION
+    };
+    if version >= min_version && version >= MIN_PROTOCOL_VERSION {
+        Some(version)
+    } else {
+        None
+    }
+}
+
+/// Pairs an [`MpidMessageWrapper`](enum.MpidMessageWrapper.html) with a request ID, allowing a
+/// Client with several outstanding requests to match each response to the request which caused it.
+///
+/// A response must carry the ID of its request, so MpidManagers should construct responses via
+/// [`response()`](#method.response).  Messages not sent in response to a request, such as a
+/// `PutHeader` forwarded between MpidManagers, can carry any ID.
+///
+/// Envelopes should be sent using [`encode()`](#method.encode) and
+/// [`decode()`](#method.decode) rather than being serialised directly, so that a peer running an
+/// older version of the protocol can report messages which it doesn't understand.
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub struct MpidEnvelope {
+    request_id: u64,
+    message: MpidMessageWrapper,
+}
+
+impl MpidEnvelope {
+    /// Constructor.
+    pub fn new(request_id: u64, message: MpidMessageWrapper) -> MpidEnvelope {
+        MpidEnvelope {
+            request_id: request_id,
+            message: message,
+        }
+    }
+
+    /// Constructs the envelope for `message` sent in response to this one, i.e. with the same
+    /// request ID.
+    pub fn response(&self, message: MpidMessageWrapper) -> MpidEnvelope {
+        MpidEnvelope::new(self.request_id, message)
+    }
+
+    /// Getter for the request ID.
+    pub fn request_id(&self) -> u64 {
+        self.request_id
+    }
+
+    /// Getter for the wrapped message.
+    pub fn message(&self) -> &MpidMessageWrapper {
+        &self.message
+    }
+
+    /// Consumes the envelope, returning the wrapped message.
+    pub fn into_message(self) -> MpidMessageWrapper {
+        self.message
+    }
+
+    /// Serialises the envelope for sending to a peer using the given protocol `version`, normally
+    /// the one agreed via [`negotiate_version()`](fn.negotiate_version.html).
+    ///
+    /// `Error::UnsupportedVersion` is returned if `version` isn't supported locally, or if the
+    /// message can't be represented in it, as per
+    /// [`MpidMessageWrapper::min_version()`](enum.MpidMessageWrapper.html#method.min_version).
+    pub fn encode(&self, version: u32) -> Result<Vec<u8>, Error> {
+        if version < MIN_PROTOCOL_VERSION || version > PROTOCOL_VERSION ||
+           version < self.message.min_version() {
+            return Err(Error::UnsupportedVersion);
+        }
+        let packet = Packet {
+            version: version,
+            request_id: self.request_id,
+            message: try!(serialise(&self.message)),
+        };
+        Ok(try!(serialise(&packet)))
+    }
+
+    /// Parses an envelope serialised via [`encode()`](#method.encode).
+    ///
+    /// `Error::UnsupportedVersion` is returned if the envelope was encoded using a version older
+    /// than [`MIN_PROTOCOL_VERSION`](constant.MIN_PROTOCOL_VERSION.html).  Envelopes encoded using
+    /// a version newer than [`PROTOCOL_VERSION`](constant.PROTOCOL_VERSION.html) are still decoded
+    /// where possible, since variants are only ever appended and so existing ones keep their
+    /// meaning.  `Error::UnknownMessage` is returned, carrying the envelope's request ID, if the
+    /// message can't be decoded, e.g. because it is a variant added in a newer version.  In either
+    /// case, the receiver can skip the message or report it to the sender via a `Failure`.
+    pub fn decode(serialised: &[u8]) -> Result<MpidEnvelope, Error> {
+        let packet = try!(deserialise::<Packet>(serialised));
+        if packet.version < MIN_PROTOCOL_VERSION {
+            return Err(Error::UnsupportedVersion);
+        }
+        match deserialise(&packet.message) {
+            Ok(message) => Ok(MpidEnvelope::new(packet.request_id, message)),
+            Err(_) => Err(Error::UnknownMessage(packet.request_id)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::Packet;
+    use maidsafe_utilities::serialisation::serialise;
+    use rand;
+    use sodiumoxide::crypto::sign;
+    use xor_name::XorName;
+    use {Error, FailureReason, MpidMessageWrapper, SignedRequest};
+
+    #[test]
+    fn response() {
+        let request_id: u64 = rand::random();
+        let name: XorName = rand::random();
+        let request = MpidEnvelope::new(request_id, MpidMessageWrapper::OutboxHas(vec![name]));
+        let response = request.response(MpidMessageWrapper::OutboxHasResponse(vec![]));
+        assert_eq!(response.request_id(), request_id);
+        assert_eq!(*response.message(), MpidMessageWrapper::OutboxHasResponse(vec![]));
+        assert_eq!(request.into_message(), MpidMessageWrapper::OutboxHas(vec![name]));
+    }
+
+    #[test]
+    fn encode_and_decode() {
+        let request_id: u64 = rand::random();
+        let envelope =
+            MpidEnvelope::new(request_id,
+                              MpidMessageWrapper::VersionedOnline(MIN_PROTOCOL_VERSION,
+                                                                  PROTOCOL_VERSION));
+        let encoded = unwrap_result!(envelope.encode(PROTOCOL_VERSION));
+        assert_eq!(unwrap_result!(MpidEnvelope::decode(&encoded)), envelope);
+        assert!(envelope.encode(PROTOCOL_VERSION + 1).is_err());
+
+        // A newer version is accepted if the message itself can be decoded.
+        let packet = Packet {
+            version: PROTOCOL_VERSION + 1,
+            request_id: request_id,
+            message: unwrap_result!(serialise(envelope.message())),
+        };
+        let encoded = unwrap_result!(serialise(&packet));
+        assert_eq!(unwrap_result!(MpidEnvelope::decode(&encoded)), envelope);
+
+        // An older version than the minimum is rejected, as is encoding using it.
+        let packet = Packet {
+            version: MIN_PROTOCOL_VERSION - 1,
+            request_id: request_id,
+            message: unwrap_result!(serialise(envelope.message())),
+        };
+        let encoded = unwrap_result!(serialise(&packet));
+        match MpidEnvelope::decode(&encoded) {
+            Err(Error::UnsupportedVersion) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        assert!(envelope.encode(MIN_PROTOCOL_VERSION - 1).is_err());
+
+        // A message which can't be decoded is reported along with its request ID.
+        let packet = Packet {
+            version: PROTOCOL_VERSION + 1,
+            request_id: request_id,
+            message: vec![255; 8],
+        };
+        let encoded = unwrap_result!(serialise(&packet));
+        match MpidEnvelope::decode(&encoded) {
+            Err(Error::UnknownMessage(id)) => assert_eq!(id, request_id),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        assert!(MpidEnvelope::decode(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn min_version() {
+        let name: XorName = rand::random();
+        let (public_key, secret_key) = sign::gen_keypair();
+
+        // The variants which predate versioning can be encoded using every supported version.
+        for message in vec![MpidMessageWrapper::Online,
+                            MpidMessageWrapper::GetOutboxHeaders,
+                            MpidMessageWrapper::DeleteHeader(name)] {
+            let envelope = MpidEnvelope::new(0, message);
+            assert!(envelope.encode(MIN_PROTOCOL_VERSION).is_ok());
+        }
+
+        // Newer variants can't be encoded using older versions, nor can older variants carrying a
+        // newer reason or signed request.
+        let unrepresentable = vec![(MpidMessageWrapper::RecallHeaderResponse(name, None), 8),
+                                   (MpidMessageWrapper::Failure(FailureReason::StorageFailure), 7),
+                                   (MpidMessageWrapper::DeleteHeadersResponse(vec![]), 6)];
+        for (message, version) in unrepresentable {
+            let envelope = MpidEnvelope::new(0, message);
+            match envelope.encode(version - 1) {
+                Err(Error::UnsupportedVersion) => (),
+                result => panic!("Unexpected result: {:?}", result),
+            }
+            assert!(envelope.encode(version).is_ok());
+        }
+        let signed = unwrap_result!(SignedRequest::new(MpidMessageWrapper::GetAccountInfo,
+                                                       1,
+                                                       &public_key,
+                                                       &secret_key));
+        let envelope = MpidEnvelope::new(0, MpidMessageWrapper::Signed(signed));
+        assert!(envelope.encode(MIN_PROTOCOL_VERSION).is_err());
+        assert!(envelope.encode(5).is_ok());
+        let envelope = MpidEnvelope::new(0, MpidMessageWrapper::Failure(FailureReason::Replayed));
+        assert!(envelope.encode(MIN_PROTOCOL_VERSION).is_ok());
+    }
+
+    #[test]
+    fn negotiation() {
+        assert_eq!(negotiate_version(MIN_PROTOCOL_VERSION, PROTOCOL_VERSION + 5),
+                   Some(PROTOCOL_VERSION));
+        assert_eq!(negotiate_version(MIN_PROTOCOL_VERSION, PROTOCOL_VERSION),
+                   Some(PROTOCOL_VERSION));
+        assert_eq!(negotiate_version(PROTOCOL_VERSION + 1, PROTOCOL_VERSION + 5), None);
+        assert_eq!(negotiate_version(0, MIN_PROTOCOL_VERSION - 1), None);
+    }
+}
@@ -18,33 +18,45 @@
 /// Maximum allowed length for a [header's `metadata`](struct.MpidHeader.html#method.new) (128
 /// bytes).
 pub const MAX_HEADER_METADATA_SIZE: usize = 128;  // bytes
+/// Tolerance allowed for differences between the clocks of the sender and other parties when
+/// checking a [header's timestamps](struct.MpidHeader.html#method.validate_time) (5 minutes).
+pub const MAX_CLOCK_SKEW: u64 = 300;  // seconds
 
 use std::fmt::{self, Debug, Formatter};
-use std::sync::{Once, ONCE_INIT};
 
-use maidsafe_utilities::serialisation::serialise;
+use maidsafe_utilities::serialisation::{deserialise, serialise};
 use rand::{self, Rng};
-use sodiumoxide;
+use sodiumoxide::crypto::box_;
 use sodiumoxide::crypto::hash::sha512;
 use sodiumoxide::crypto::sign::{self, PublicKey, SecretKey, Signature};
 use super::{Error, GUID_SIZE};
+use super::encryption;
 use xor_name::XorName;
 
-static INITIALISE_SODIUMOXIDE: Once = ONCE_INIT;
-static mut sodiumoxide_init_result: bool = false;
-
 #[derive(PartialEq, Eq, Hash, Clone, RustcDecodable, RustcEncodable)]
 struct Detail {
-    sender: XorName,
     guid: [u8; GUID_SIZE],
     metadata: Vec<u8>,
+    metadata_sealed: bool,
+    recipient: XorName,
+    body_hash: XorName,
+    created_at: u64,
+    expires_at: Option<u64>,
+}
+
+// The sender's name and their signature of the name and `Detail`.  For sealed-sender headers, both
+// are serialised and encrypted to the recipient so that only it can tell who wrote the header.
+#[derive(PartialEq, Eq, Hash, Clone, RustcDecodable, RustcEncodable)]
+enum Sender {
+    Visible(XorName, Signature),
+    Sealed(Vec<u8>),
 }
 
 /// Minimal information about a given message which can be used as a notification to the receiver.
 #[derive(PartialEq, Eq, Hash, Clone, RustcDecodable, RustcEncodable)]
 pub struct MpidHeader {
     detail: Detail,
-    signature: Signature,
+    sender: Sender,
 }
 
 impl MpidHeader {
@@ -59,36 +71,165 @@ impl MpidHeader {
     /// [`MAX_HEADER_METADATA_SIZE`](constant.MAX_HEADER_METADATA_SIZE.html).  It can be empty if
     /// desired.
     ///
-    /// `secret_key` will be used to generate a signature of `sender`, `guid` and `metadata`.
+    /// `recipient` and `body` are the intended receiver and the body of the message which the
+    /// header represents.  The header stores `recipient` and the SHA512 hash of `body`, so that it
+    /// can't be used to represent any other message.  See
+    /// [`matches_body()`](#method.matches_body).  Headers constructed by
+    /// [`MpidMessage`](struct.MpidMessage.html) pass the message's serialised body, attachments and
+    /// encrypted key as `body`, so that none of these can be replaced.
+    ///
+    /// The current time is recorded as the header's creation time, accessed via the
+    /// [`created_at()`](#method.created_at) getter.  If `time_to_live` is provided, the header
+    /// will expire that many seconds later.  See [`is_expired()`](#method.is_expired).
+    ///
+    /// `secret_key` will be used to generate a signature of `sender`, `guid`, `metadata`,
+    /// `recipient`, the hash of `body` and the creation and expiry times.
     ///
     /// An error will be returned if `metadata` exceeds `MAX_HEADER_METADATA_SIZE` or if
     /// serialisation during the signing process fails.
     pub fn new(sender: XorName,
                metadata: Vec<u8>,
+               recipient: XorName,
+               body: &[u8],
+               time_to_live: Option<u64>,
                secret_key: &SecretKey)
                -> Result<MpidHeader, Error> {
-        assert!(Self::initialise_sodiumoxide());
+        Self::create(sender,
+                     metadata,
+                     recipient,
+                     body,
+                     time_to_live,
+                     None,
+                     false,
+                     secret_key)
+    }
+
+    /// Constructor for a header whose metadata can only be read by the recipient.
+    ///
+    /// The arguments are as per [`new()`](#method.new), except that `metadata` is encrypted to
+    /// `recipient_public_key` before being signed.  Only the holder of the corresponding secret key
+    /// can retrieve the original metadata via [`open_metadata()`](#method.open_metadata).
+    ///
+    /// The encrypted form of `metadata` is 72 bytes larger than the original, and it is this which
+    /// must not exceed [`MAX_HEADER_METADATA_SIZE`](constant.MAX_HEADER_METADATA_SIZE.html).
+    pub fn new_with_sealed_metadata(sender: XorName,
+                                    metadata: Vec<u8>,
+                                    recipient: XorName,
+                                    body: &[u8],
+                                    time_to_live: Option<u64>,
+                                    recipient_public_key: &box_::PublicKey,
+                                    secret_key: &SecretKey)
+                                    -> Result<MpidHeader, Error> {
+        Self::create(sender,
+                     metadata,
+                     recipient,
+                     body,
+                     time_to_live,
+                     Some(recipient_public_key),
+                     false,
+                     secret_key)
+    }
+
+    /// Constructor for a header whose sender can only be identified by the recipient.
+    ///
+    /// The arguments are as per
+    /// [`new_with_sealed_metadata()`](#method.new_with_sealed_metadata).  As well as `metadata`,
+    /// `sender` and the signature are encrypted to `recipient_public_key`, so
+    /// [`sender()`](#method.sender) and [`signature()`](#method.signature) return `None`.  Any
+    /// party can still use the header's [`name()`](#method.name) to store and account for it, but
+    /// only the recipient can find out the sender via [`open_sender()`](#method.open_sender) and
+    /// validate the signature via [`verify_sealed_sender()`](#method.verify_sealed_sender).
+    pub fn new_with_sealed_sender(sender: XorName,
+                                  metadata: Vec<u8>,
+                                  recipient: XorName,
+                                  body: &[u8],
+                                  time_to_live: Option<u64>,
+                                  recipient_public_key: &box_::PublicKey,
+                                  secret_key: &SecretKey)
+                                  -> Result<MpidHeader, Error> {
+        Self::create(sender,
+                     metadata,
+                     recipient,
+                     body,
+                     time_to_live,
+                     Some(recipient_public_key),
+                     true,
+                     secret_key)
+    }
+
+    #[cfg_attr(feature="clippy", allow(too_many_arguments))]
+    fn create(sender: XorName,
+              metadata: Vec<u8>,
+              recipient: XorName,
+              body: &[u8],
+              time_to_live: Option<u64>,
+              recipient_public_key: Option<&box_::PublicKey>,
+              seal_sender: bool,
+              secret_key: &SecretKey)
+              -> Result<MpidHeader, Error> {
+        assert!(::initialise_sodiumoxide());
+        let (metadata, metadata_sealed) = match recipient_public_key {
+            Some(public_key) => (encryption::seal(&metadata, public_key), true),
+            None => (metadata, false),
+        };
         if metadata.len() > MAX_HEADER_METADATA_SIZE {
             return Err(Error::MetadataTooLarge);
         }
 
+        let created_at = ::seconds_since_epoch();
         let mut detail = Detail {
-            sender: sender,
             guid: [0u8; GUID_SIZE],
             metadata: metadata,
+            metadata_sealed: metadata_sealed,
+            recipient: recipient,
+            body_hash: hash_body(body),
+            created_at: created_at,
+            expires_at: time_to_live.map(|time_to_live| created_at.saturating_add(time_to_live)),
         };
         rand::thread_rng().fill_bytes(&mut detail.guid);
 
-        let encoded = try!(serialise(&detail));
+        let encoded = try!(serialise(&(&sender, &detail)));
+        let signature = sign::sign_detached(&encoded, secret_key);
+        let sender = match recipient_public_key {
+            Some(public_key) if seal_sender => {
+                let encoded_sender = try!(serialise(&(sender, signature)));
+                Sender::Sealed(encryption::seal(&encoded_sender, public_key))
+            }
+            _ => Sender::Visible(sender, signature),
+        };
         Ok(MpidHeader {
             detail: detail,
-            signature: sign::sign_detached(&encoded, secret_key),
+            sender: sender,
         })
     }
 
-    /// The name of the original creator of the message.
-    pub fn sender(&self) -> &XorName {
-        &self.detail.sender
+    /// The name of the original creator of the message, or `None` if the header was constructed
+    /// via [`new_with_sealed_sender()`](#method.new_with_sealed_sender).
+    pub fn sender(&self) -> Option<&XorName> {
+        match self.sender {
+            Sender::Visible(ref sender, _) => Some(sender),
+            Sender::Sealed(_) => None,
+        }
+    }
+
+    /// Whether the sender is encrypted, i.e. whether the header was constructed via
+    /// [`new_with_sealed_sender()`](#method.new_with_sealed_sender).
+    pub fn is_sender_sealed(&self) -> bool {
+        match self.sender {
+            Sender::Visible(..) => false,
+            Sender::Sealed(_) => true,
+        }
+    }
+
+    /// Decrypts and returns the name of the sender of a header constructed via
+    /// [`new_with_sealed_sender()`](#method.new_with_sealed_sender).  The signature is not checked;
+    /// use [`verify_sealed_sender()`](#method.verify_sealed_sender) for that.
+    ///
+    /// An error will be returned if the sender isn't sealed, or if `recipient_secret_key` doesn't
+    /// correspond to the public key used to seal it.
+    pub fn open_sender(&self, recipient_secret_key: &box_::SecretKey) -> Result<XorName, Error> {
+        let (sender, _) = try!(self.open_sealed_sender(recipient_secret_key));
+        Ok(sender)
     }
 
     /// A unique identifier generated randomly when calling `new()`.
@@ -96,14 +237,99 @@ impl MpidHeader {
         &self.detail.guid
     }
 
-    /// Arbitrary, user-supplied information.
+    /// Arbitrary, user-supplied information.  If the header was constructed via
+    /// [`new_with_sealed_metadata()`](#method.new_with_sealed_metadata) or
+    /// [`new_with_sealed_sender()`](#method.new_with_sealed_sender), this is the encrypted form.
     pub fn metadata(&self) -> &Vec<u8> {
         &self.detail.metadata
     }
 
-    /// The signature of `sender`, `guid` and `metadata`, created when calling `new()`.
-    pub fn signature(&self) -> &Signature {
-        &self.signature
+    /// Whether the metadata is encrypted, i.e. whether the header was constructed via
+    /// [`new_with_sealed_metadata()`](#method.new_with_sealed_metadata) or
+    /// [`new_with_sealed_sender()`](#method.new_with_sealed_sender).
+    pub fn is_metadata_sealed(&self) -> bool {
+        self.detail.metadata_sealed
+    }
+
+    /// Decrypts and returns the metadata of a header constructed via
+    /// [`new_with_sealed_metadata()`](#method.new_with_sealed_metadata) or
+    /// [`new_with_sealed_sender()`](#method.new_with_sealed_sender).
+    ///
+    /// An error will be returned if the metadata isn't sealed, or if `recipient_secret_key` doesn't
+    /// correspond to the public key used to seal it.
+    pub fn open_metadata(&self, recipient_secret_key: &box_::SecretKey) -> Result<Vec<u8>, Error> {
+        if !self.detail.metadata_sealed {
+            return Err(Error::NotEncrypted);
+        }
+        encryption::open(&self.detail.metadata, recipient_secret_key)
+    }
+
+    /// The name of the intended receiver of the message which the header represents.
+    pub fn recipient(&self) -> &XorName {
+        &self.detail.recipient
+    }
+
+    /// The SHA512 hash of the body of the message which the header represents.  For a header
+    /// constructed by [`MpidMessage`](struct.MpidMessage.html), this covers the message's
+    /// attachments and encrypted key as well as its body.
+    pub fn body_hash(&self) -> &XorName {
+        &self.detail.body_hash
+    }
+
+    /// Returns whether the header represents a message with the given `recipient` and `body`,
+    /// i.e. whether they match the recipient and body hash which were signed when the header was
+    /// constructed.
+    pub fn matches_body(&self, recipient: &XorName, body: &[u8]) -> bool {
+        self.detail.recipient == *recipient && self.detail.body_hash == hash_body(body)
+    }
+
+    /// The time at which the header was created, in seconds since the UNIX epoch.
+    pub fn created_at(&self) -> u64 {
+        self.detail.created_at
+    }
+
+    /// The time at which the header expires, in seconds since the UNIX epoch, or `None` if it was
+    /// created without a `time_to_live`.
+    pub fn expires_at(&self) -> Option<u64> {
+        self.detail.expires_at
+    }
+
+    /// Returns whether the header has expired at time `now` (in seconds since the UNIX epoch).
+    ///
+    /// To allow for clock differences, a header is only deemed to have expired once `now` is more
+    /// than [`MAX_CLOCK_SKEW`](constant.MAX_CLOCK_SKEW.html) beyond its expiry time.  A header
+    /// without an expiry time never expires.
+    pub fn is_expired(&self, now: u64) -> bool {
+        match self.detail.expires_at {
+            Some(expires_at) => now > expires_at.saturating_add(MAX_CLOCK_SKEW),
+            None => false,
+        }
+    }
+
+    /// Checks the header's timestamps against `now` (in seconds since the UNIX epoch).
+    ///
+    /// This is intended for use by MpidManagers before accepting a new header or message.  It
+    /// returns `Error::Expired` if [`is_expired(now)`](#method.is_expired) is true, or
+    /// `Error::InvalidTimestamp` if the header claims to have been created more than
+    /// [`MAX_CLOCK_SKEW`](constant.MAX_CLOCK_SKEW.html) in the future.
+    pub fn validate_time(&self, now: u64) -> Result<(), Error> {
+        if self.detail.created_at > now.saturating_add(MAX_CLOCK_SKEW) {
+            return Err(Error::InvalidTimestamp);
+        }
+        if self.is_expired(now) {
+            return Err(Error::Expired);
+        }
+        Ok(())
+    }
+
+    /// The signature of `sender`, `guid`, `metadata`, `recipient`, the body hash and timestamps,
+    /// created when calling `new()`, or `None` if
+    /// the header was constructed via [`new_with_sealed_sender()`](#method.new_with_sealed_sender).
+    pub fn signature(&self) -> Option<&Signature> {
+        match self.sender {
+            Sender::Visible(_, ref signature) => Some(signature),
+            Sender::Sealed(_) => None,
+        }
     }
 
     /// The name of the header.  This is a relatively expensive getter - the name is the SHA512 hash
@@ -114,32 +340,111 @@ impl MpidHeader {
     }
 
     /// Validates the header's signature against the provided `PublicKey`.
+    ///
+    /// This always fails for a header constructed via
+    /// [`new_with_sealed_sender()`](#method.new_with_sealed_sender), since the signature is only
+    /// available to the recipient.  Use [`verify_sealed_sender()`](#method.verify_sealed_sender)
+    /// in that case.
     pub fn verify(&self, public_key: &PublicKey) -> bool {
-        match serialise(&self.detail) {
-            Ok(encoded) => sign::verify_detached(&self.signature, &encoded, public_key),
+        match self.sender {
+            Sender::Visible(ref sender, ref signature) => {
+                self.verify_signature(sender, signature, public_key)
+            }
+            Sender::Sealed(_) => false,
+        }
+    }
+
+    /// Validates the header's signature against the provided `PublicKey` as per
+    /// [`verify()`](#method.verify), and also checks that the sender's name is the one derived from
+    /// `public_key` via [`name_from_public_key()`](fn.name_from_public_key.html).
+    ///
+    /// This prevents a header signed by one account from claiming to have been sent by another.
+    pub fn verify_sender(&self, public_key: &PublicKey) -> bool {
+        match self.sender {
+            Sender::Visible(ref sender, _) => {
+                *sender == ::name_from_public_key(public_key) && self.verify(public_key)
+            }
+            Sender::Sealed(_) => false,
+        }
+    }
+
+    /// Decrypts the sender and signature of a header constructed via
+    /// [`new_with_sealed_sender()`](#method.new_with_sealed_sender) and validates the signature
+    /// against the provided `PublicKey`.  As per [`verify_sender()`](#method.verify_sender), the
+    /// decrypted sender's name must also be the one derived from `public_key`.
+    pub fn verify_sealed_sender(&self,
+                                recipient_secret_key: &box_::SecretKey,
+                                public_key: &PublicKey)
+                                -> bool {
+        match self.open_sealed_sender(recipient_secret_key) {
+            Ok((sender, signature)) => {
+                sender == ::name_from_public_key(public_key) &&
+                self.verify_signature(&sender, &signature, public_key)
+            }
             Err(_) => false,
         }
     }
 
-    #[allow(unsafe_code)]
-    fn initialise_sodiumoxide() -> bool {
-        unsafe {
-            INITIALISE_SODIUMOXIDE.call_once(|| {
-                sodiumoxide_init_result = sodiumoxide::init();
-            });
-            sodiumoxide_init_result
+    fn open_sealed_sender(&self,
+                          recipient_secret_key: &box_::SecretKey)
+                          -> Result<(XorName, Signature), Error> {
+        let sealed = match self.sender {
+            Sender::Visible(..) => return Err(Error::NotEncrypted),
+            Sender::Sealed(ref sealed) => sealed,
+        };
+        let encoded_sender = try!(encryption::open(sealed, recipient_secret_key));
+        Ok(try!(deserialise(&encoded_sender)))
+    }
+
+    fn verify_signature(&self,
+                        sender: &XorName,
+                        signature: &Signature,
+                        public_key: &PublicKey)
+                        -> bool {
+        match serialise(&(sender, &self.detail)) {
+            Ok(encoded) => sign::verify_detached(signature, &encoded, public_key),
+            Err(_) => false,
         }
     }
 }
 
+fn hash_body(body: &[u8]) -> XorName {
+    XorName(sha512::hash(body).0)
+}
+
 impl Debug for MpidHeader {
     fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
-        write!(formatter,
-               "MpidHeader {{ sender: {:?}, guid: {}, metadata: {}, signature: {} }}",
-               self.detail.sender,
-               ::format_binary_array(&self.detail.guid),
-               ::format_binary_array(&self.detail.metadata),
-               ::format_binary_array(&self.signature))
+        match self.sender {
+            Sender::Visible(ref sender, ref signature) => {
+                write!(formatter,
+                       "MpidHeader {{ sender: {:?}, guid: {}, metadata: {}, metadata_sealed: {}, \
+                        recipient: {:?}, body_hash: {:?}, created_at: {}, expires_at: {:?}, \
+                        signature: {} }}",
+                       sender,
+                       ::format_binary_array(&self.detail.guid),
+                       ::format_binary_array(&self.detail.metadata),
+                       self.detail.metadata_sealed,
+                       self.detail.recipient,
+                       self.detail.body_hash,
+                       self.detail.created_at,
+                       self.detail.expires_at,
+                       ::format_binary_array(signature))
+            }
+            Sender::Sealed(ref sealed) => {
+                write!(formatter,
+                       "MpidHeader {{ sealed_sender: {}, guid: {}, metadata: {}, \
+                        metadata_sealed: {}, recipient: {:?}, body_hash: {:?}, created_at: {}, \
+                        expires_at: {:?} }}",
+                       ::format_binary_array(sealed),
+                       ::format_binary_array(&self.detail.guid),
+                       ::format_binary_array(&self.detail.metadata),
+                       self.detail.metadata_sealed,
+                       self.detail.recipient,
+                       self.detail.body_hash,
+                       self.detail.created_at,
+                       self.detail.expires_at)
+            }
+        }
     }
 }
 
@@ -147,24 +452,42 @@ impl Debug for MpidHeader {
 mod test {
     use super::*;
     use rand;
-    use sodiumoxide::crypto::sign;
+    use sodiumoxide::crypto::{box_, sign};
     use xor_name::XorName;
 
     #[test]
     fn full() {
         let (mut public_key, secret_key) = sign::gen_keypair();
         let sender: XorName = rand::random();
+        let recipient: XorName = rand::random();
+        let body = ::generate_random_bytes(100);
 
         // Check with metadata which is empty, then at size limit, then just above limit.
         {
-            let header = unwrap_result!(MpidHeader::new(sender.clone(), vec![], &secret_key));
+            let header = unwrap_result!(MpidHeader::new(sender.clone(),
+                                                        vec![],
+                                                        recipient.clone(),
+                                                        &body,
+                                                        None,
+                                                        &secret_key));
             assert!(header.metadata().is_empty());
         }
         let mut metadata = ::generate_random_bytes(MAX_HEADER_METADATA_SIZE);
-        let header = unwrap_result!(MpidHeader::new(sender.clone(), metadata.clone(), &secret_key));
+        let header = unwrap_result!(MpidHeader::new(sender.clone(),
+                                                    metadata.clone(),
+                                                    recipient.clone(),
+                                                    &body,
+                                                    None,
+                                                    &secret_key));
         assert!(*header.metadata() == metadata);
         metadata.push(0);
-        assert!(MpidHeader::new(sender.clone(), metadata.clone(), &secret_key).is_err());
+        assert!(MpidHeader::new(sender.clone(),
+                                metadata.clone(),
+                                recipient.clone(),
+                                &body,
+                                None,
+                                &secret_key)
+                    .is_err());
         let _ = metadata.pop();
 
         // Check verify function with a valid and invalid key
@@ -180,12 +503,18 @@ mod test {
         // different GUIDs and signatures.
         let header1 = unwrap_result!(MpidHeader::new(sender.clone(),
                                                      metadata.clone(),
+                                                     recipient.clone(),
+                                                     &body,
+                                                     None,
                                                      &secret_key));
         let header2 = unwrap_result!(MpidHeader::new(sender.clone(),
                                                      metadata.clone(),
+                                                     recipient.clone(),
+                                                     &body,
+                                                     None,
                                                      &secret_key));
         assert!(header1 != header2);
-        assert_eq!(*header1.sender(), sender);
+        assert_eq!(header1.sender(), Some(&sender));
         assert_eq!(header1.sender(), header2.sender());
         assert_eq!(*header1.metadata(), metadata);
         assert_eq!(header1.metadata(), header2.metadata());
@@ -195,4 +524,191 @@ mod test {
         let name2 = unwrap_result!(header2.name());
         assert!(name1 != name2);
     }
+
+    #[test]
+    fn matches_body() {
+        let (_, secret_key) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let recipient: XorName = rand::random();
+        let other_recipient: XorName = rand::random();
+        let body = ::generate_random_bytes(100);
+        let other_body = ::generate_random_bytes(100);
+
+        let header = unwrap_result!(MpidHeader::new(sender,
+                                                    vec![],
+                                                    recipient.clone(),
+                                                    &body,
+                                                    None,
+                                                    &secret_key));
+        assert_eq!(*header.recipient(), recipient);
+        assert!(header.matches_body(&recipient, &body));
+        assert!(!header.matches_body(&other_recipient, &body));
+        assert!(!header.matches_body(&recipient, &other_body));
+        assert!(!header.matches_body(&recipient, &[]));
+    }
+
+    #[test]
+    fn sealed_metadata() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let (recipient_public_key, recipient_secret_key) = box_::gen_keypair();
+        let (_, wrong_secret_key) = box_::gen_keypair();
+        let sender: XorName = rand::random();
+        let recipient: XorName = rand::random();
+        let metadata = ::generate_random_bytes(MAX_HEADER_METADATA_SIZE - 72);
+
+        let header = unwrap_result!(MpidHeader::new_with_sealed_metadata(sender.clone(),
+                                                                         metadata.clone(),
+                                                                         recipient.clone(),
+                                                                         &[],
+                                                                         None,
+                                                                         &recipient_public_key,
+                                                                         &secret_key));
+        assert!(header.is_metadata_sealed());
+        assert_eq!(header.metadata().len(), MAX_HEADER_METADATA_SIZE);
+        assert!(header.verify(&public_key));
+        assert_eq!(unwrap_result!(header.open_metadata(&recipient_secret_key)), metadata);
+        assert!(header.open_metadata(&wrong_secret_key).is_err());
+
+        // The size limit applies to the sealed form.
+        let mut too_large = metadata.clone();
+        too_large.push(0);
+        assert!(MpidHeader::new_with_sealed_metadata(sender.clone(),
+                                                     too_large,
+                                                     recipient.clone(),
+                                                     &[],
+                                                     None,
+                                                     &recipient_public_key,
+                                                     &secret_key)
+                    .is_err());
+
+        // Plain metadata can't be opened.
+        let header = unwrap_result!(MpidHeader::new(sender,
+                                                    metadata,
+                                                    recipient,
+                                                    &[],
+                                                    None,
+                                                    &secret_key));
+        assert!(!header.is_metadata_sealed());
+        assert!(header.open_metadata(&recipient_secret_key).is_err());
+    }
+
+    #[test]
+    fn sealed_sender() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let (other_public_key, _) = sign::gen_keypair();
+        let (recipient_public_key, recipient_secret_key) = box_::gen_keypair();
+        let (_, wrong_secret_key) = box_::gen_keypair();
+        let sender = ::name_from_public_key(&public_key);
+        let recipient: XorName = rand::random();
+        let metadata = ::generate_random_bytes(10);
+
+        let header = unwrap_result!(MpidHeader::new_with_sealed_sender(sender.clone(),
+                                                                       metadata.clone(),
+                                                                       recipient.clone(),
+                                                                       &[],
+                                                                       None,
+                                                                       &recipient_public_key,
+                                                                       &secret_key));
+        assert!(header.is_sender_sealed());
+        assert!(header.sender().is_none());
+        assert!(header.signature().is_none());
+        assert!(header.is_metadata_sealed());
+        assert!(!header.verify(&public_key));
+        assert!(header.matches_body(&recipient, &[]));
+        let _ = unwrap_result!(header.name());
+
+        assert_eq!(unwrap_result!(header.open_sender(&recipient_secret_key)), sender);
+        assert!(header.open_sender(&wrong_secret_key).is_err());
+        assert_eq!(unwrap_result!(header.open_metadata(&recipient_secret_key)), metadata);
+        assert!(header.verify_sealed_sender(&recipient_secret_key, &public_key));
+        assert!(!header.verify_sealed_sender(&recipient_secret_key, &other_public_key));
+        assert!(!header.verify_sealed_sender(&wrong_secret_key, &public_key));
+
+        // A visible sender can't be opened.
+        let header = unwrap_result!(MpidHeader::new(sender,
+                                                    metadata,
+                                                    recipient,
+                                                    &[],
+                                                    None,
+                                                    &secret_key));
+        assert!(!header.is_sender_sealed());
+        assert!(header.open_sender(&recipient_secret_key).is_err());
+        assert!(!header.verify_sealed_sender(&recipient_secret_key, &public_key));
+    }
+
+    #[test]
+    fn verify_sender() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let (recipient_public_key, recipient_secret_key) = box_::gen_keypair();
+        let sender = ::name_from_public_key(&public_key);
+        let forged_sender: XorName = rand::random();
+        let recipient: XorName = rand::random();
+
+        let header = unwrap_result!(MpidHeader::new(sender.clone(),
+                                                    vec![],
+                                                    recipient.clone(),
+                                                    &[],
+                                                    None,
+                                                    &secret_key));
+        assert!(header.verify_sender(&public_key));
+
+        // A header claiming a sender name which doesn't match the signing key has a valid signature
+        // but fails sender verification.
+        let header = unwrap_result!(MpidHeader::new(forged_sender.clone(),
+                                                    vec![],
+                                                    recipient.clone(),
+                                                    &[],
+                                                    None,
+                                                    &secret_key));
+        assert!(header.verify(&public_key));
+        assert!(!header.verify_sender(&public_key));
+
+        let header = unwrap_result!(MpidHeader::new_with_sealed_sender(forged_sender,
+                                                                       vec![],
+                                                                       recipient,
+                                                                       &[],
+                                                                       None,
+                                                                       &recipient_public_key,
+                                                                       &secret_key));
+        assert!(!header.verify_sender(&public_key));
+        assert!(!header.verify_sealed_sender(&recipient_secret_key, &public_key));
+    }
+
+    #[test]
+    fn timestamps() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let recipient: XorName = rand::random();
+
+        let header = unwrap_result!(MpidHeader::new(sender.clone(),
+                                                    vec![],
+                                                    recipient.clone(),
+                                                    &[],
+                                                    None,
+                                                    &secret_key));
+        let created_at = header.created_at();
+        assert!(header.expires_at().is_none());
+        assert!(!header.is_expired(u64::max_value()));
+        assert!(header.validate_time(created_at).is_ok());
+        assert!(header.validate_time(created_at - MAX_CLOCK_SKEW).is_ok());
+        assert!(header.validate_time(created_at - MAX_CLOCK_SKEW - 1).is_err());
+
+        let header = unwrap_result!(MpidHeader::new(sender,
+                                                    vec![],
+                                                    recipient,
+                                                    &[],
+                                                    Some(60),
+                                                    &secret_key));
+        assert!(header.verify(&public_key));
+        let expires_at = unwrap_option!(header.expires_at(), "");
+        assert!(expires_at >= header.created_at() + 60);
+        assert!(!header.is_expired(expires_at));
+        assert!(!header.is_expired(expires_at + MAX_CLOCK_SKEW));
+        assert!(header.is_expired(expires_at + MAX_CLOCK_SKEW + 1));
+        assert!(header.validate_time(expires_at + MAX_CLOCK_SKEW).is_ok());
+        match header.validate_time(expires_at + MAX_CLOCK_SKEW + 1) {
+            Err(Error::Expired) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
 }
@@ -0,0 +1,1470 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::collections::HashMap;
+
+use sodiumoxide::crypto::sign::PublicKey;
+use super::{AccountInfo, Error, FailureReason, Inbox, MailboxStore, MpidEnvelope, MpidHeader,
+            MpidMessage, MpidMessageWrapper, MpidMultiMessage, Outbox, PresenceTracker,
+            RecallOutcome, RequestVerifier, SignedRequest, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+            negotiate_version};
+use super::mailbox_store::NullStore;
+use xor_name::XorName;
+
+/// The source or destination of a message handled by an [`MpidManager`](struct.MpidManager.html).
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub enum Authority {
+    /// The Client with the given name.
+    Client(XorName),
+    /// The MpidManagers of the account with the given name.
+    Managers(XorName),
+}
+
+/// An action to be carried out by the vault on behalf of an
+/// [`MpidManager`](struct.MpidManager.html).
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Action {
+    /// Send `envelope` from the MpidManagers of the account `src` to `dst`.
+    Send {
+        /// The account on whose behalf the envelope is sent.
+        src: XorName,
+        /// The recipient of the envelope.
+        dst: Authority,
+        /// The envelope to send.
+        envelope: MpidEnvelope,
+        /// The protocol version with which to [encode](struct.MpidEnvelope.html#method.encode)
+        /// the envelope.
+        version: u32,
+    },
+}
+
+struct Account {
+    inbox: Inbox,
+    outbox: Outbox,
+    // The account whose MpidManagers delivered each header in the inbox, keyed by header name.
+    // The owner's `GetMessage`s are forwarded there, which also serves headers with sealed senders.
+    senders: HashMap<XorName, XorName>,
+}
+
+impl Account {
+    fn new() -> Account {
+        Account {
+            inbox: Inbox::new(),
+            outbox: Outbox::new(),
+            senders: HashMap::new(),
+        }
+    }
+}
+
+// Collects the messages resulting from a single incoming envelope, sent on behalf of `account`.
+// Every outgoing envelope carries the request ID of the incoming one.
+struct Outgoing {
+    account: XorName,
+    request_id: u64,
+    messages: Vec<(Authority, MpidMessageWrapper)>,
+}
+
+impl Outgoing {
+    fn send(&mut self, dst: Authority, message: MpidMessageWrapper) {
+        self.messages.push((dst, message));
+    }
+}
+
+// A `Recall` awaiting a `RecallHeaderResponse` from the recipient's MpidManagers.
+struct PendingRecall {
+    recipient: XorName,
+    request_id: u64,
+    recalled_at: u64,
+}
+
+/// A network-agnostic implementation of the MpidManagers' side of the protocol.
+///
+/// The vault passes each incoming envelope to [`handle()`](#method.handle) along with its source
+/// and the account it is addressed to, and carries out the returned actions.  All times are in
+/// seconds since the UNIX epoch.
+///
+/// Requests from Clients must be wrapped in a
+/// [`MpidMessageWrapper::Signed`](enum.MpidMessageWrapper.html#variant.Signed); others are
+/// rejected with a `Failure(Unauthorised)`.  Messages from other MpidManagers are trusted to have
+/// been authenticated by the network.  Messages which are misdirected or unexpected from their
+/// source are ignored.
+///
+/// A header is only accepted from the MpidManagers of its sender, and those MpidManagers are
+/// recorded alongside it so that the recipient's `GetMessage` can be forwarded to them.  For a
+/// header with a sealed sender, this record is the only route to the message; the header itself is
+/// authenticated by the sender's MpidManagers, which check the message signature covering it.
+///
+/// Each outgoing envelope carries the request ID of the incoming one, and is to be encoded using
+/// the version given alongside it.  For a Client, this is the version agreed in its last
+/// `VersionedOnline`, or [`MIN_PROTOCOL_VERSION`](constant.MIN_PROTOCOL_VERSION.html) if it hasn't
+/// negotiated one, and a message which that version can't represent is replaced with a
+/// `Failure(UnsupportedVersion)`.  Other MpidManagers are assumed to support
+/// [`PROTOCOL_VERSION`](constant.PROTOCOL_VERSION.html).
+///
+/// Every change to an inbox or outbox is written through to a
+/// [`MailboxStore`](trait.MailboxStore.html) before being acknowledged.  If the store fails, the
+/// change is abandoned and the request fails with `StorageFailure`.
+pub struct MpidManager {
+    accounts: HashMap<XorName, Account>,
+    // Recalls awaiting confirmation, keyed by the owner and header name.
+    recalls: HashMap<(XorName, XorName), PendingRecall>,
+    recall_timeout: u64,
+    presence: PresenceTracker,
+    // The protocol version negotiated with each Client.
+    versions: HashMap<XorName, u32>,
+    verifier: RequestVerifier,
+    store: Box<MailboxStore>,
+}
+
+impl MpidManager {
+    /// Constructor.  Clients are deemed disconnected if they send neither `VersionedOnline` nor
+    /// `KeepAlive` within `presence_timeout` seconds.  Mailboxes are held only in memory.
+    pub fn new(presence_timeout: u64) -> MpidManager {
+        MpidManager {
+            accounts: HashMap::new(),
+            recalls: HashMap::new(),
+            recall_timeout: presence_timeout,
+            presence: PresenceTracker::new(presence_timeout),
+            versions: HashMap::new(),
+            verifier: RequestVerifier::new(),
+            store: Box::new(NullStore),
+        }
+    }
+
+    /// As per [`new()`](#method.new), but persisting mailboxes to `store` and restoring any
+    /// already held in it.
+    ///
+    /// Restored inboxes and outboxes account for their contents exactly as before, so the limits
+    /// [`MAX_INBOX_SIZE`](constant.MAX_INBOX_SIZE.html) and
+    /// [`MAX_OUTBOX_SIZE`](constant.MAX_OUTBOX_SIZE.html) continue to apply across restarts.
+    /// Headers which were queued for disconnected Clients are not restored, but remain listed via
+    /// `GetInboxHeaders`.
+    ///
+    /// The nonce of each signed request is persisted before the request is handled, so requests
+    /// handled before a restart can't be replayed after it.  This costs a synced write to the store
+    /// for every signed request, including `KeepAlive`s.
+    pub fn with_store(store: Box<MailboxStore>,
+                      presence_timeout: u64)
+                      -> Result<MpidManager, Error> {
+        let mut accounts = HashMap::new();
+        for (name, stored) in try!(store.load()) {
+            let mut account = Account::new();
+            let mut message_names = Vec::new();
+            for message in stored.messages() {
+                message_names.push(try!(account.outbox.put(message.clone())));
+            }
+            for multi_message in stored.multi_messages() {
+                message_names.extend(try!(account.outbox.put_multi(multi_message.clone())));
+            }
+            for message_name in message_names {
+                if stored.is_retrieved(&message_name) {
+                    let _ = account.outbox.retrieve(&message_name);
+                }
+            }
+            for (header, sender) in stored.headers() {
+                let header_name = try!(account.inbox.put(header.clone()));
+                let _ = account.senders.insert(header_name, sender.clone());
+            }
+            let _ = accounts.insert(name, account);
+        }
+        let mut verifier = RequestVerifier::new();
+        for (requester, nonce) in try!(store.load_nonces()) {
+            verifier.record(requester, nonce);
+        }
+        Ok(MpidManager {
+            accounts: accounts,
+            recalls: HashMap::new(),
+            recall_timeout: presence_timeout,
+            presence: PresenceTracker::new(presence_timeout),
+            versions: HashMap::new(),
+            verifier: verifier,
+            store: store,
+        })
+    }
+
+    /// Handles `envelope`, sent from `src` to the MpidManagers of the account `dst`, returning the
+    /// resulting actions.
+    pub fn handle(&mut self,
+                  src: Authority,
+                  dst: XorName,
+                  envelope: MpidEnvelope,
+                  now: u64)
+                  -> Vec<Action> {
+        let mut outgoing = Outgoing {
+            account: dst,
+            request_id: envelope.request_id(),
+            messages: Vec::new(),
+        };
+        match src {
+            Authority::Client(client) => {
+                self.handle_client_request(client, envelope.into_message(), now, &mut outgoing)
+            }
+            Authority::Managers(managers) => {
+                self.handle_managers_message(managers, envelope.into_message(), now, &mut outgoing)
+            }
+        }
+        let Outgoing { account, request_id, messages } = outgoing;
+        messages.into_iter()
+                .map(|(dst, message)| self.action(&account, request_id, dst, message))
+                .collect()
+    }
+
+    /// Marks as disconnected every Client whose connection has timed out by `now`.  Headers for
+    /// them are queued until their next `VersionedOnline`.
+    ///
+    /// Also abandons any `Recall` whose recipient's MpidManagers haven't confirmed dropping the
+    /// header within the same timeout, returning the `RecallFailure(TimedOut)` to send to each
+    /// such Client.  The message remains in the outbox, so the Client can recall it again.
+    pub fn expire(&mut self, now: u64) -> Vec<Action> {
+        let _ = self.presence.expire(now);
+        let expired = self.recalls
+                          .iter()
+                          .filter(|&(_, pending)| {
+                              pending.recalled_at.saturating_add(self.recall_timeout) <= now
+                          })
+                          .map(|(key, _)| key.clone())
+                          .collect::<Vec<_>>();
+        let mut actions = Vec::new();
+        for (owner, name) in expired {
+            if let Some(pending) = self.recalls.remove(&(owner.clone(), name.clone())) {
+                let failure = MpidMessageWrapper::RecallFailure(name, FailureReason::TimedOut);
+                actions.push(self.action(&owner,
+                                         pending.request_id,
+                                         Authority::Client(owner.clone()),
+                                         failure));
+            }
+        }
+        actions
+    }
+
+    /// Returns the usage of the given account, or `None` if it holds no messages or headers.
+    pub fn account_info(&self, account: &XorName) -> Option<AccountInfo> {
+        self.accounts
+            .get(account)
+            .map(|account| AccountInfo::new(account.inbox.usage(), account.outbox.usage()))
+    }
+
+    // Looks up an account without creating it, so that requests naming arbitrary accounts don't
+    // leave empty ones behind.
+    fn account(&self, account: &XorName) -> Option<&Account> {
+        self.accounts.get(account)
+    }
+
+    // As per `account()`, but applies `f` to an empty account if there is none.
+    fn with_account<T, F>(&self, account: &XorName, f: F) -> T
+        where F: FnOnce(&Account) -> T
+    {
+        match self.accounts.get(account) {
+            Some(account) => f(account),
+            None => f(&Account::new()),
+        }
+    }
+
+    // Looks up an account, creating it if need be.  Only used when adding to the account.
+    fn account_entry(&mut self, account: &XorName) -> &mut Account {
+        self.accounts.entry(account.clone()).or_insert_with(Account::new)
+    }
+
+    // Drops the account if it no longer holds any messages or headers.
+    fn remove_if_empty(&mut self, account: &XorName) {
+        let is_empty = self.accounts
+                           .get(account)
+                           .map_or(false, |account| {
+                               account.inbox.is_empty() && account.outbox.is_empty()
+                           });
+        if is_empty {
+            let _ = self.accounts.remove(account);
+        }
+    }
+
+    // Wraps `message` for sending from the MpidManagers of `src` to `dst`, using the version
+    // negotiated with `dst`.
+    fn action(&self,
+              src: &XorName,
+              request_id: u64,
+              dst: Authority,
+              message: MpidMessageWrapper)
+              -> Action {
+        let version = match dst {
+            Authority::Client(ref client) => {
+                self.versions.get(client).cloned().unwrap_or(MIN_PROTOCOL_VERSION)
+            }
+            Authority::Managers(_) => PROTOCOL_VERSION,
+        };
+        let message = if message.min_version() > version {
+            MpidMessageWrapper::Failure(FailureReason::UnsupportedVersion)
+        } else {
+            message
+        };
+        Action::Send {
+            src: src.clone(),
+            dst: dst,
+            envelope: MpidEnvelope::new(request_id, message),
+            version: version,
+        }
+    }
+
+    fn handle_client_request(&mut self,
+                             client: XorName,
+                             message: MpidMessageWrapper,
+                             now: u64,
+                             outgoing: &mut Outgoing) {
+        let reply_to = Authority::Client(client.clone());
+        let signed = match message {
+            MpidMessageWrapper::Signed(signed) => signed,
+            _ => {
+                return outgoing.send(reply_to,
+                                     MpidMessageWrapper::Failure(FailureReason::Unauthorised))
+            }
+        };
+        match self.verifier.check(&signed) {
+            Ok(ref requester) if *requester == client => (),
+            Ok(_) => {
+                return outgoing.send(reply_to,
+                                     MpidMessageWrapper::Failure(FailureReason::Unauthorised))
+            }
+            Err(error) => {
+                return outgoing.send(reply_to,
+                                     MpidMessageWrapper::Failure(FailureReason::from(&error)))
+            }
+        }
+        if let Err(error) = self.store.put_nonce(&client, signed.nonce()) {
+            return outgoing.send(reply_to,
+                                 MpidMessageWrapper::Failure(FailureReason::from(&error)));
+        }
+        self.verifier.record(client.clone(), signed.nonce());
+
+        // Requests from a message's recipient to the sender's MpidManagers.
+        match *signed.request() {
+            MpidMessageWrapper::DeleteHeader(ref name) => {
+                if let Err(error) = self.delete_header(&client, name, outgoing) {
+                    let reason = FailureReason::from(&error);
+                    let failure = MpidMessageWrapper::DeleteHeaderFailure(name.clone(), reason);
+                    outgoing.send(reply_to, failure);
+                }
+                return;
+            }
+            MpidMessageWrapper::DeleteHeaders(ref names) => {
+                let results = names.iter()
+                                   .map(|name| {
+                                       let result = self.delete_header(&client, name, outgoing);
+                                       (name.clone(),
+                                        result.err().map(|error| FailureReason::from(&error)))
+                                   })
+                                   .collect();
+                return outgoing.send(reply_to, MpidMessageWrapper::DeleteHeadersResponse(results));
+            }
+            MpidMessageWrapper::PutReceipt(ref receipt) => {
+                if *receipt.recipient() == client && receipt.verify(signed.public_key()) {
+                    let owner = Authority::Client(outgoing.account.clone());
+                    outgoing.send(owner, MpidMessageWrapper::PutReceipt(receipt.clone()));
+                } else {
+                    outgoing.send(reply_to,
+                                  MpidMessageWrapper::Failure(FailureReason::InvalidSignature));
+                }
+                return;
+            }
+            _ => (),
+        }
+
+        // All other requests must be made by the account's owner.
+        if client != outgoing.account {
+            return outgoing.send(reply_to,
+                                 MpidMessageWrapper::Failure(FailureReason::Unauthorised));
+        }
+        self.handle_owner_request(&signed, now, outgoing)
+    }
+
+    fn handle_owner_request(&mut self,
+                            signed: &SignedRequest,
+                            now: u64,
+                            outgoing: &mut Outgoing) {
+        let owner = outgoing.account.clone();
+        let reply_to = Authority::Client(owner.clone());
+        let response = match *signed.request() {
+            // An unversioned `Online` offers only version 1, which is no longer supported.
+            MpidMessageWrapper::Online => {
+                Some(MpidMessageWrapper::Failure(FailureReason::UnsupportedVersion))
+            }
+            MpidMessageWrapper::VersionedOnline(min_version, max_version) => {
+                match negotiate_version(min_version, max_version) {
+                    Some(version) => {
+                        let _ = self.versions.insert(owner.clone(), version);
+                        outgoing.send(reply_to.clone(),
+                                      MpidMessageWrapper::OnlineResponse(version));
+                        for name in self.presence.online(owner.clone(), now) {
+                            let header = self.account(&owner)
+                                             .and_then(|account| account.inbox.get(&name));
+                            if let Some(header) = header {
+                                outgoing.send(reply_to.clone(),
+                                              MpidMessageWrapper::PutHeader(header.clone()));
+                            }
+                        }
+                        None
+                    }
+                    None => Some(MpidMessageWrapper::Failure(FailureReason::UnsupportedVersion)),
+                }
+            }
+            MpidMessageWrapper::Offline => {
+                self.presence.offline(&owner);
+                None
+            }
+            MpidMessageWrapper::KeepAlive => {
+                if self.presence.keep_alive(&owner, now) {
+                    None
+                } else {
+                    Some(MpidMessageWrapper::Failure(FailureReason::InvalidRequest))
+                }
+            }
+            MpidMessageWrapper::PutMessage(ref message) => {
+                match message.name() {
+                    Ok(name) => {
+                        self.put_message(message.clone(), signed.public_key(), now, outgoing)
+                            .err()
+                            .map(|error| {
+                                MpidMessageWrapper::PutMessageFailure(name,
+                                                                      FailureReason::from(&error))
+                            })
+                    }
+                    Err(error) => Some(MpidMessageWrapper::Failure(FailureReason::from(&error))),
+                }
+            }
+            MpidMessageWrapper::PutMultiMessage(ref multi_message) => {
+                let names = multi_message.headers()
+                                         .into_iter()
+                                         .filter_map(|header| header.name().ok())
+                                         .collect::<Vec<_>>();
+                match self.put_multi_message(multi_message.clone(),
+                                             signed.public_key(),
+                                             now,
+                                             outgoing) {
+                    Ok(()) => None,
+                    Err(error) => {
+                        let reason = FailureReason::from(&error);
+                        if names.is_empty() {
+                            Some(MpidMessageWrapper::Failure(reason))
+                        } else {
+                            for name in names {
+                                outgoing.send(reply_to.clone(),
+                                              MpidMessageWrapper::PutMessageFailure(name, reason));
+                            }
+                            None
+                        }
+                    }
+                }
+            }
+            MpidMessageWrapper::PutMessages(ref messages) => {
+                let mut results = Vec::with_capacity(messages.len());
+                for message in messages {
+                    if let Ok(name) = message.name() {
+                        let result =
+                            self.put_message(message.clone(), signed.public_key(), now, outgoing);
+                        results.push((name, result.err().map(|error| FailureReason::from(&error))));
+                    }
+                }
+                Some(MpidMessageWrapper::PutMessagesResponse(results))
+            }
+            MpidMessageWrapper::GetMessage(ref header) => {
+                match header.name() {
+                    Ok(ref name) if *header.recipient() != owner => {
+                        Some(MpidMessageWrapper::GetMessageFailure(name.clone(),
+                                                                   FailureReason::InvalidRequest))
+                    }
+                    Ok(name) => {
+                        let sender = self.account(&owner)
+                                         .and_then(|account| account.senders.get(&name))
+                                         .or_else(|| header.sender())
+                                         .cloned();
+                        match sender {
+                            Some(sender) => {
+                                outgoing.send(Authority::Managers(sender),
+                                              MpidMessageWrapper::GetMessage(header.clone()));
+                                None
+                            }
+                            None => {
+                                Some(MpidMessageWrapper::GetMessageFailure(name,
+                                                                           FailureReason::NotFound))
+                            }
+                        }
+                    }
+                    Err(error) => Some(MpidMessageWrapper::Failure(FailureReason::from(&error))),
+                }
+            }
+            MpidMessageWrapper::OutboxHas(ref names) => {
+                let has = self.with_account(&owner, |account| account.outbox.has(names));
+                Some(MpidMessageWrapper::OutboxHasResponse(has))
+            }
+            MpidMessageWrapper::GetOutboxHeaders => {
+                let headers = self.with_account(&owner, |account| account.outbox.all_headers());
+                Some(MpidMessageWrapper::GetOutboxHeadersResponse(headers))
+            }
+            MpidMessageWrapper::GetOutboxHeadersPage(ref request) => {
+                Some(match self.with_account(&owner, |account| account.outbox.headers(request)) {
+                    Ok(page) => MpidMessageWrapper::GetOutboxHeadersPageResponse(page),
+                    Err(error) => MpidMessageWrapper::Failure(FailureReason::from(&error)),
+                })
+            }
+            MpidMessageWrapper::InboxHas(ref names) => {
+                let has = self.with_account(&owner, |account| account.inbox.has(names));
+                Some(MpidMessageWrapper::InboxHasResponse(has))
+            }
+            MpidMessageWrapper::GetInboxHeaders(ref request) => {
+                Some(match self.with_account(&owner, |account| account.inbox.headers(request)) {
+                    Ok(page) => MpidMessageWrapper::GetInboxHeadersResponse(page),
+                    Err(error) => MpidMessageWrapper::Failure(FailureReason::from(&error)),
+                })
+            }
+            MpidMessageWrapper::DeleteMessage(ref name) => {
+                self.delete_message(&owner, name).err().map(|error| {
+                    MpidMessageWrapper::DeleteMessageFailure(name.clone(),
+                                                             FailureReason::from(&error))
+                })
+            }
+            MpidMessageWrapper::DeleteMessages(ref names) => {
+                let results = names.iter()
+                                   .map(|name| {
+                                       let result = self.delete_message(&owner, name);
+                                       (name.clone(),
+                                        result.err().map(|error| FailureReason::from(&error)))
+                                   })
+                                   .collect();
+                Some(MpidMessageWrapper::DeleteMessagesResponse(results))
+            }
+            MpidMessageWrapper::Recall(ref name) => self.recall(signed, name, now, outgoing),
+            MpidMessageWrapper::GetAccountInfo => {
+                let info = self.with_account(&owner, |account| {
+                    AccountInfo::new(account.inbox.usage(), account.outbox.usage())
+                });
+                Some(MpidMessageWrapper::GetAccountInfoResponse(info))
+            }
+            _ => Some(MpidMessageWrapper::Failure(FailureReason::InvalidRequest)),
+        };
+        if let Some(response) = response {
+            outgoing.send(reply_to, response);
+        }
+    }
+
+    fn handle_managers_message(&mut self,
+                               managers: XorName,
+                               message: MpidMessageWrapper,
+                               now: u64,
+                               outgoing: &mut Outgoing) {
+        let account = outgoing.account.clone();
+        match message {
+            MpidMessageWrapper::PutHeader(header) => {
+                let name = match header.name() {
+                    Ok(name) => name,
+                    Err(_) => return,
+                };
+                if *header.recipient() != account ||
+                   header.sender().map_or(false, |sender| *sender != managers) {
+                    return;
+                }
+                let is_duplicate = self.account(&account)
+                                       .map_or(false, |account| account.inbox.get(&name).is_some());
+                if is_duplicate {
+                    return;
+                }
+                let result = match header.validate_time(now) {
+                    Ok(()) => self.store_header(&account, header.clone(), &managers),
+                    Err(error) => Err(error),
+                };
+                match result {
+                    Ok(_) => {
+                        if self.presence.deliver(&account, name, now) {
+                            outgoing.send(Authority::Client(account),
+                                          MpidMessageWrapper::PutHeader(header));
+                        }
+                    }
+                    Err(error) => {
+                        let reason = FailureReason::from(&error);
+                        outgoing.send(Authority::Managers(managers),
+                                      MpidMessageWrapper::PutHeaderFailure(name, reason))
+                    }
+                }
+            }
+            MpidMessageWrapper::PutHeaderFailure(name, reason) => {
+                let is_recipient = self.account(&account)
+                                       .and_then(|account| account.outbox.header(&name))
+                                       .map_or(false, |header| *header.recipient() == managers);
+                if is_recipient && self.remove_message(&account, &name).is_ok() {
+                    outgoing.send(Authority::Client(account),
+                                  MpidMessageWrapper::PutMessageFailure(name, reason));
+                }
+            }
+            MpidMessageWrapper::GetMessage(header) => {
+                let name = match header.name() {
+                    Ok(name) => name,
+                    Err(_) => return,
+                };
+                if *header.recipient() != managers {
+                    return;
+                }
+                let response = match self.retrieve_message(&account, &name, &managers) {
+                    Ok(message) => MpidMessageWrapper::PutMessage(message),
+                    Err(error) => {
+                        MpidMessageWrapper::GetMessageFailure(name, FailureReason::from(&error))
+                    }
+                };
+                outgoing.send(Authority::Client(managers), response);
+            }
+            MpidMessageWrapper::RecallHeader(signed) => {
+                let name = match *signed.request() {
+                    MpidMessageWrapper::Recall(ref name) => name.clone(),
+                    _ => return,
+                };
+                let authorised = self.account(&account).and_then(|account| {
+                    match (account.inbox.get(&name), account.senders.get(&name)) {
+                        (Some(header), Some(sender)) => {
+                            Some(*sender == managers && signed.authorises_recall(header, sender))
+                        }
+                        _ => None,
+                    }
+                });
+                let reason = match authorised {
+                    Some(true) => {
+                        match self.remove_header(&account, &name) {
+                            Ok(_) => {
+                                let _ = self.presence.remove_queued(&account, &name);
+                                None
+                            }
+                            Err(error) => Some(FailureReason::from(&error)),
+                        }
+                    }
+                    Some(false) => Some(FailureReason::Unauthorised),
+                    None => None,
+                };
+                outgoing.send(Authority::Managers(managers),
+                              MpidMessageWrapper::RecallHeaderResponse(name, reason));
+            }
+            MpidMessageWrapper::RecallHeaderResponse(name, reason) => {
+                let key = (account.clone(), name.clone());
+                match self.recalls.get(&key) {
+                    Some(pending) if pending.recipient == managers => (),
+                    _ => return,
+                }
+                let _ = self.recalls.remove(&key);
+                let response = match reason {
+                    None => self.complete_recall(&account, name),
+                    Some(reason) => MpidMessageWrapper::RecallFailure(name, reason),
+                };
+                outgoing.send(Authority::Client(account), response);
+            }
+            _ => (),
+        }
+    }
+
+    // Stores a message from the account's owner in its outbox and notifies the recipient's
+    // MpidManagers.
+    fn put_message(&mut self,
+                   message: MpidMessage,
+                   public_key: &PublicKey,
+                   now: u64,
+                   outgoing: &mut Outgoing)
+                   -> Result<(), Error> {
+        if !message.verify(public_key) {
+            return Err(Error::InvalidSignature);
+        }
+        if message.header().sender().map_or(false, |sender| *sender != outgoing.account) {
+            return Err(Error::Unauthorised);
+        }
+        try!(message.header().validate_time(now));
+        let header = message.header().clone();
+        let recipient = message.recipient().clone();
+        let owner = outgoing.account.clone();
+        try!(self.store_message(&owner, message));
+        outgoing.send(Authority::Managers(recipient),
+                      MpidMessageWrapper::PutHeader(header));
+        Ok(())
+    }
+
+    // As per `put_message()`, but for a multi-recipient message, which is stored once and announced
+    // to each recipient's MpidManagers.  If any recipient's header is invalid, none are stored.
+    fn put_multi_message(&mut self,
+                         message: MpidMultiMessage,
+                         public_key: &PublicKey,
+                         now: u64,
+                         outgoing: &mut Outgoing)
+                         -> Result<(), Error> {
+        if message.is_empty() {
+            return Err(Error::InvalidRecipients);
+        }
+        if !message.verify(public_key) {
+            return Err(Error::InvalidSignature);
+        }
+        for header in message.headers() {
+            if header.sender().map_or(false, |sender| *sender != outgoing.account) {
+                return Err(Error::Unauthorised);
+            }
+            try!(header.validate_time(now));
+        }
+        let headers = message.headers().into_iter().cloned().collect::<Vec<_>>();
+        let owner = outgoing.account.clone();
+        try!(self.store_multi_message(&owner, message));
+        for header in headers {
+            outgoing.send(Authority::Managers(header.recipient().clone()),
+                          MpidMessageWrapper::PutHeader(header));
+        }
+        Ok(())
+    }
+
+    // Removes the named message from the owner's inbox or outbox.
+    fn delete_message(&mut self, owner: &XorName, name: &XorName) -> Result<(), Error> {
+        if try!(self.remove_header(owner, name)).is_some() {
+            let _ = self.presence.remove_queued(owner, name);
+            return Ok(());
+        }
+        match try!(self.remove_message(owner, name)) {
+            Some(_) => Ok(()),
+            None => Err(Error::NotFound),
+        }
+    }
+
+    // Removes the named message from the account's outbox on behalf of its recipient.
+    fn delete_header(&mut self,
+                     recipient: &XorName,
+                     name: &XorName,
+                     outgoing: &Outgoing)
+                     -> Result<(), Error> {
+        match self.account(&outgoing.account)
+                  .and_then(|account| account.outbox.header(name))
+                  .map(|header| header.recipient() == recipient) {
+            Some(true) => (),
+            Some(false) => return Err(Error::Unauthorised),
+            None => return Err(Error::NotFound),
+        }
+        let _ = try!(self.remove_message(&outgoing.account, name));
+        Ok(())
+    }
+
+    // Asks the recipient's MpidManagers to drop the header of the named message in the owner's
+    // outbox.  The message is kept until they confirm via a `RecallHeaderResponse`, so that its
+    // header never remains in the recipient's inbox without it.  A repeated recall supersedes any
+    // pending one.
+    fn recall(&mut self,
+              signed: &SignedRequest,
+              name: &XorName,
+              now: u64,
+              outgoing: &mut Outgoing)
+              -> Option<MpidMessageWrapper> {
+        let owner = outgoing.account.clone();
+        let recipient = match self.account(&owner).and_then(|account| account.outbox.header(name)) {
+            Some(header) => header.recipient().clone(),
+            None => {
+                return Some(MpidMessageWrapper::RecallFailure(name.clone(),
+                                                              FailureReason::NotFound))
+            }
+        };
+        let pending = PendingRecall {
+            recipient: recipient.clone(),
+            request_id: outgoing.request_id,
+            recalled_at: now,
+        };
+        let _ = self.recalls.insert((owner, name.clone()), pending);
+        outgoing.send(Authority::Managers(recipient),
+                      MpidMessageWrapper::RecallHeader(signed.clone()));
+        None
+    }
+
+    // Removes a recalled message from the owner's outbox once its recipient's MpidManagers have
+    // confirmed dropping its header, returning the response for the owner.
+    fn complete_recall(&mut self, owner: &XorName, name: XorName) -> MpidMessageWrapper {
+        let is_retrieved = self.account(owner)
+                               .map_or(false, |account| account.outbox.is_retrieved(&name));
+        let outcome = if is_retrieved {
+            RecallOutcome::AlreadyRetrieved
+        } else {
+            RecallOutcome::Recalled
+        };
+        match self.remove_message(owner, &name) {
+            Ok(Some(_)) => MpidMessageWrapper::RecallResponse(name, outcome),
+            Ok(None) => MpidMessageWrapper::RecallFailure(name, FailureReason::NotFound),
+            Err(error) => MpidMessageWrapper::RecallFailure(name, FailureReason::from(&error)),
+        }
+    }
+
+    // Adds `message` to the owner's outbox and writes it to the store, leaving the outbox
+    // unchanged if either fails.
+    fn store_message(&mut self, owner: &XorName, message: MpidMessage) -> Result<(), Error> {
+        let name = try!(message.name());
+        if self.account_entry(owner).outbox.contains(&name) {
+            return Ok(());
+        }
+        let _ = try!(self.account_entry(owner).outbox.put(message.clone()));
+        if let Err(error) = self.store.put_message(owner, &name, &message) {
+            let _ = self.account_entry(owner).outbox.remove(&name);
+            self.remove_if_empty(owner);
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    // As per `store_message()`, but for a multi-recipient message.
+    fn store_multi_message(&mut self,
+                           owner: &XorName,
+                           message: MpidMultiMessage)
+                           -> Result<(), Error> {
+        let first_name = match message.headers().first() {
+            Some(header) => try!(header.name()),
+            None => return Err(Error::InvalidRecipients),
+        };
+        if self.account_entry(owner).outbox.contains(&first_name) {
+            return Ok(());
+        }
+        let names = try!(self.account_entry(owner).outbox.put_multi(message.clone()));
+        if let Err(error) = self.store.put_multi_message(owner, &names, &message) {
+            for name in &names {
+                let _ = self.account_entry(owner).outbox.remove(name);
+            }
+            self.remove_if_empty(owner);
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    // As per `store_message()`, but for a header in the owner's inbox delivered by the MpidManagers
+    // of `sender`.
+    fn store_header(&mut self,
+                    owner: &XorName,
+                    header: MpidHeader,
+                    sender: &XorName)
+                    -> Result<(), Error> {
+        let name = try!(self.account_entry(owner).inbox.put(header.clone()));
+        if let Err(error) = self.store.put_header(owner, &name, &header, sender) {
+            let _ = self.account_entry(owner).inbox.remove(&name);
+            self.remove_if_empty(owner);
+            return Err(error);
+        }
+        let _ = self.account_entry(owner).senders.insert(name, sender.clone());
+        Ok(())
+    }
+
+    // Marks the named message in the owner's outbox as retrieved by `recipient`, returning it.
+    fn retrieve_message(&mut self,
+                        owner: &XorName,
+                        name: &XorName,
+                        recipient: &XorName)
+                        -> Result<MpidMessage, Error> {
+        let already_retrieved = {
+            let outbox = match self.account(owner) {
+                Some(account) => &account.outbox,
+                None => return Err(Error::NotFound),
+            };
+            match outbox.header(name) {
+                Some(header) if header.recipient() == recipient => outbox.is_retrieved(name),
+                _ => return Err(Error::NotFound),
+            }
+        };
+        if !already_retrieved {
+            try!(self.store.mark_retrieved(owner, name));
+        }
+        self.accounts
+            .get_mut(owner)
+            .and_then(|account| account.outbox.retrieve(name))
+            .ok_or(Error::NotFound)
+    }
+
+    // Removes the named message from the owner's outbox, first removing it from the store.
+    fn remove_message(&mut self,
+                      owner: &XorName,
+                      name: &XorName)
+                      -> Result<Option<MpidMessage>, Error> {
+        if !self.account(owner).map_or(false, |account| account.outbox.contains(name)) {
+            return Ok(None);
+        }
+        try!(self.store.remove_message(owner, name));
+        let message = self.accounts.get_mut(owner).and_then(|account| account.outbox.remove(name));
+        self.remove_if_empty(owner);
+        Ok(message)
+    }
+
+    // As per `remove_message()`, but for a header in the owner's inbox.
+    fn remove_header(&mut self,
+                     owner: &XorName,
+                     name: &XorName)
+                     -> Result<Option<MpidHeader>, Error> {
+        if self.account(owner).and_then(|account| account.inbox.get(name)).is_none() {
+            return Ok(None);
+        }
+        try!(self.store.remove_header(owner, name));
+        let header = self.accounts.get_mut(owner).and_then(|account| {
+            let _ = account.senders.remove(name);
+            account.inbox.remove(name)
+        });
+        self.remove_if_empty(owner);
+        Ok(header)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand;
+    use sodiumoxide::crypto::box_;
+    use sodiumoxide::crypto::sign::{self, PublicKey, SecretKey};
+    use std::cell::Cell;
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+    use std::io;
+    use std::rc::Rc;
+    use xor_name::XorName;
+    use {AppendLogStore, Error, FailureReason, MailboxStore, MemoryStore, MpidEnvelope, MpidHeader,
+         MpidMessage, MpidMessageWrapper, MpidMultiMessage, RecallOutcome, SignedRequest,
+         StoredAccount, PROTOCOL_VERSION};
+
+    struct TestClient {
+        name: XorName,
+        public_key: PublicKey,
+        secret_key: SecretKey,
+        nonce: u64,
+    }
+
+    impl TestClient {
+        fn new() -> TestClient {
+            let (public_key, secret_key) = sign::gen_keypair();
+            TestClient {
+                name: ::name_from_public_key(&public_key),
+                public_key: public_key,
+                secret_key: secret_key,
+                nonce: 0,
+            }
+        }
+
+        fn sign(&mut self, request: MpidMessageWrapper) -> MpidEnvelope {
+            self.nonce += 1;
+            let signed = unwrap_result!(SignedRequest::new(request,
+                                                           self.nonce,
+                                                           &self.public_key,
+                                                           &self.secret_key));
+            MpidEnvelope::new(self.nonce, MpidMessageWrapper::Signed(signed))
+        }
+
+        fn new_message(&self, recipient: &TestClient) -> MpidMessage {
+            unwrap_result!(MpidMessage::new(self.name.clone(),
+                                            vec![],
+                                            recipient.name.clone(),
+                                            vec![1, 2, 3],
+                                            vec![],
+                                            None,
+                                            &self.secret_key))
+        }
+    }
+
+    // Passes `envelope` from `client` to the MpidManagers of `dst`, then routes all resulting
+    // messages between MpidManagers, returning those sent to Clients.
+    fn send(manager: &mut MpidManager,
+            client: &TestClient,
+            dst: &XorName,
+            envelope: MpidEnvelope)
+            -> Vec<(XorName, MpidMessageWrapper)> {
+        let now = ::seconds_since_epoch();
+        let mut pending = manager.handle(Authority::Client(client.name.clone()),
+                                         dst.clone(),
+                                         envelope,
+                                         now);
+        let mut to_clients = Vec::new();
+        while !pending.is_empty() {
+            let Action::Send { src, dst, envelope, version } = pending.remove(0);
+            let encoded = unwrap_result!(envelope.encode(version));
+            let envelope = unwrap_result!(MpidEnvelope::decode(&encoded));
+            match dst {
+                Authority::Client(name) => to_clients.push((name, envelope.into_message())),
+                Authority::Managers(name) => {
+                    pending.extend(manager.handle(Authority::Managers(src), name, envelope, now))
+                }
+            }
+        }
+        to_clients
+    }
+
+    fn header_name(header: &MpidHeader) -> XorName {
+        unwrap_result!(header.name())
+    }
+
+    // A `MemoryStore` which fails to record nonces while `fail_nonces` is set.
+    struct FailingStore {
+        store: MemoryStore,
+        fail_nonces: Rc<Cell<bool>>,
+    }
+
+    impl MailboxStore for FailingStore {
+        fn put_message(&mut self,
+                       account: &XorName,
+                       name: &XorName,
+                       message: &MpidMessage)
+                       -> Result<(), Error> {
+            self.store.put_message(account, name, message)
+        }
+
+        fn put_multi_message(&mut self,
+                             account: &XorName,
+                             names: &[XorName],
+                             message: &MpidMultiMessage)
+                             -> Result<(), Error> {
+            self.store.put_multi_message(account, names, message)
+        }
+
+        fn mark_retrieved(&mut self, account: &XorName, name: &XorName) -> Result<(), Error> {
+            self.store.mark_retrieved(account, name)
+        }
+
+        fn remove_message(&mut self, account: &XorName, name: &XorName) -> Result<(), Error> {
+            self.store.remove_message(account, name)
+        }
+
+        fn put_header(&mut self,
+                      account: &XorName,
+                      name: &XorName,
+                      header: &MpidHeader,
+                      sender: &XorName)
+                      -> Result<(), Error> {
+            self.store.put_header(account, name, header, sender)
+        }
+
+        fn remove_header(&mut self, account: &XorName, name: &XorName) -> Result<(), Error> {
+            self.store.remove_header(account, name)
+        }
+
+        fn put_nonce(&mut self, requester: &XorName, nonce: u64) -> Result<(), Error> {
+            if self.fail_nonces.get() {
+                return Err(Error::Io(io::Error::new(io::ErrorKind::Other, "put_nonce failed")));
+            }
+            self.store.put_nonce(requester, nonce)
+        }
+
+        fn load(&self) -> Result<HashMap<XorName, StoredAccount>, Error> {
+            self.store.load()
+        }
+
+        fn load_nonces(&self) -> Result<HashMap<XorName, u64>, Error> {
+            self.store.load_nonces()
+        }
+    }
+
+    #[test]
+    fn full() {
+        let mut manager = MpidManager::new(60);
+        let mut alice = TestClient::new();
+        let mut bob = TestClient::new();
+        let alice_name = alice.name.clone();
+        let bob_name = bob.name.clone();
+
+        // Alice comes online and sends a message to Bob, who is offline.
+        let online = alice.sign(MpidMessageWrapper::VersionedOnline(1, PROTOCOL_VERSION));
+        let online_response = MpidMessageWrapper::OnlineResponse(PROTOCOL_VERSION);
+        assert_eq!(send(&mut manager, &alice, &alice_name, online),
+                   vec![(alice_name.clone(), online_response.clone())]);
+        let message = alice.new_message(&bob);
+        let put = alice.sign(MpidMessageWrapper::PutMessage(message.clone()));
+        assert!(send(&mut manager, &alice, &alice_name, put).is_empty());
+        let info = unwrap_option!(manager.account_info(&bob_name), "");
+        assert_eq!(info.inbox().message_count(), 1);
+
+        // Bob comes online and is sent the queued header.
+        let put_header = MpidMessageWrapper::PutHeader(message.header().clone());
+        let online = bob.sign(MpidMessageWrapper::VersionedOnline(1, PROTOCOL_VERSION));
+        assert_eq!(send(&mut manager, &bob, &bob_name, online),
+                   vec![(bob_name.clone(), online_response),
+                        (bob_name.clone(), put_header)]);
+
+        // Bob retrieves the message via his MpidManagers.
+        let get = bob.sign(MpidMessageWrapper::GetMessage(message.header().clone()));
+        assert_eq!(send(&mut manager, &bob, &bob_name, get),
+                   vec![(bob_name.clone(), MpidMessageWrapper::PutMessage(message.clone()))]);
+
+        // Alice can't delete the header on Bob's behalf, but Bob can.
+        let name = header_name(message.header());
+        let delete = alice.sign(MpidMessageWrapper::DeleteHeader(name.clone()));
+        assert_eq!(send(&mut manager, &alice, &alice_name, delete),
+                   vec![(alice_name.clone(),
+                         MpidMessageWrapper::DeleteHeaderFailure(name.clone(),
+                                                                 FailureReason::Unauthorised))]);
+        let delete = bob.sign(MpidMessageWrapper::DeleteHeader(name.clone()));
+        assert!(send(&mut manager, &bob, &alice_name, delete).is_empty());
+        assert!(manager.account_info(&alice_name).is_none());
+        let delete = bob.sign(MpidMessageWrapper::DeleteMessage(name.clone()));
+        assert!(send(&mut manager, &bob, &bob_name, delete).is_empty());
+        assert!(manager.account_info(&bob_name).is_none());
+    }
+
+    #[test]
+    fn recall() {
+        let mut manager = MpidManager::new(60);
+        let mut alice = TestClient::new();
+        let mut bob = TestClient::new();
+        let alice_name = alice.name.clone();
+        let bob_name = bob.name.clone();
+
+        // Until Alice negotiates a version, responses which the minimum version can't represent are
+        // replaced with a failure.
+        let get_info = alice.sign(MpidMessageWrapper::GetAccountInfo);
+        let unsupported = MpidMessageWrapper::Failure(FailureReason::UnsupportedVersion);
+        assert_eq!(send(&mut manager, &alice, &alice_name, get_info),
+                   vec![(alice_name.clone(), unsupported)]);
+        let online = alice.sign(MpidMessageWrapper::VersionedOnline(1, PROTOCOL_VERSION));
+        let _ = send(&mut manager, &alice, &alice_name, online);
+
+        // Bob is online, so the header is pushed to him immediately.
+        let online = bob.sign(MpidMessageWrapper::VersionedOnline(1, PROTOCOL_VERSION));
+        let _ = send(&mut manager, &bob, &bob_name, online);
+        let message = alice.new_message(&bob);
+        let put = alice.sign(MpidMessageWrapper::PutMessage(message.clone()));
+        let put_header = MpidMessageWrapper::PutHeader(message.header().clone());
+        assert_eq!(send(&mut manager, &alice, &alice_name, put),
+                   vec![(bob_name.clone(), put_header)]);
+
+        // Alice recalls the message before Bob retrieves it.
+        let name = header_name(message.header());
+        let recall = alice.sign(MpidMessageWrapper::Recall(name.clone()));
+        assert_eq!(send(&mut manager, &alice, &alice_name, recall),
+                   vec![(alice_name.clone(),
+                         MpidMessageWrapper::RecallResponse(name.clone(),
+                                                            RecallOutcome::Recalled))]);
+        assert!(manager.account_info(&bob_name).is_none());
+        assert!(manager.account_info(&alice_name).is_none());
+
+        // A second recall fails.
+        let recall = alice.sign(MpidMessageWrapper::Recall(name.clone()));
+        assert_eq!(send(&mut manager, &alice, &alice_name, recall),
+                   vec![(alice_name.clone(),
+                         MpidMessageWrapper::RecallFailure(name, FailureReason::NotFound))]);
+
+        // A message with a sealed sender can also be recalled from Bob's inbox.
+        let (bob_public_key, _) = box_::gen_keypair();
+        let message = unwrap_result!(MpidMessage::new_with_sealed_sender(alice_name.clone(),
+                                                                         vec![],
+                                                                         bob_name.clone(),
+                                                                         vec![1, 2, 3],
+                                                                         vec![],
+                                                                         None,
+                                                                         &bob_public_key,
+                                                                         &alice.secret_key));
+        let put = alice.sign(MpidMessageWrapper::PutMessage(message.clone()));
+        assert_eq!(send(&mut manager, &alice, &alice_name, put).len(), 1);
+        let name = header_name(message.header());
+        let recall = alice.sign(MpidMessageWrapper::Recall(name.clone()));
+        assert_eq!(send(&mut manager, &alice, &alice_name, recall),
+                   vec![(alice_name.clone(),
+                         MpidMessageWrapper::RecallResponse(name, RecallOutcome::Recalled))]);
+        assert!(manager.account_info(&bob_name).is_none());
+
+        // The message remains in Alice's outbox until Bob's MpidManagers confirm the recall, and
+        // is kept if they report a failure.
+        let message = alice.new_message(&bob);
+        let put = alice.sign(MpidMessageWrapper::PutMessage(message.clone()));
+        let _ = send(&mut manager, &alice, &alice_name, put);
+        let name = header_name(message.header());
+        let recall = alice.sign(MpidMessageWrapper::Recall(name.clone()));
+        let request_id = recall.request_id();
+        let now = ::seconds_since_epoch();
+        let actions = manager.handle(Authority::Client(alice_name.clone()),
+                                     alice_name.clone(),
+                                     recall,
+                                     now);
+        assert_eq!(actions.len(), 1);
+        let info = unwrap_option!(manager.account_info(&alice_name), "");
+        assert_eq!(info.outbox().message_count(), 1);
+        let failure = MpidMessageWrapper::RecallHeaderResponse(name.clone(),
+                                                               Some(FailureReason::Unauthorised));
+        let actions = manager.handle(Authority::Managers(bob_name.clone()),
+                                     alice_name.clone(),
+                                     MpidEnvelope::new(request_id, failure),
+                                     now);
+        let recall_failure = MpidMessageWrapper::RecallFailure(name.clone(),
+                                                               FailureReason::Unauthorised);
+        assert_eq!(actions,
+                   vec![Action::Send {
+                            src: alice_name.clone(),
+                            dst: Authority::Client(alice_name.clone()),
+                            envelope: MpidEnvelope::new(request_id, recall_failure),
+                            version: PROTOCOL_VERSION,
+                        }]);
+        let info = unwrap_option!(manager.account_info(&alice_name), "");
+        assert_eq!(info.outbox().message_count(), 1);
+
+        // If Bob's MpidManagers don't reply in time, Alice is told that the recall timed out, and a
+        // late reply is ignored.
+        let recall = alice.sign(MpidMessageWrapper::Recall(name.clone()));
+        let request_id = recall.request_id();
+        let mut actions = manager.handle(Authority::Client(alice_name.clone()),
+                                         alice_name.clone(),
+                                         recall,
+                                         now);
+        assert_eq!(actions.len(), 1);
+        let Action::Send { dst, envelope, .. } = actions.remove(0);
+        assert_eq!(dst, Authority::Managers(bob_name.clone()));
+        assert!(manager.expire(now + 59).is_empty());
+        let timed_out = MpidMessageWrapper::RecallFailure(name.clone(), FailureReason::TimedOut);
+        assert_eq!(manager.expire(now + 60),
+                   vec![Action::Send {
+                            src: alice_name.clone(),
+                            dst: Authority::Client(alice_name.clone()),
+                            envelope: MpidEnvelope::new(request_id, timed_out),
+                            version: PROTOCOL_VERSION,
+                        }]);
+        let mut actions = manager.handle(Authority::Managers(alice_name.clone()),
+                                         bob_name.clone(),
+                                         envelope,
+                                         now + 60);
+        assert_eq!(actions.len(), 1);
+        let Action::Send { envelope, .. } = actions.remove(0);
+        assert!(manager.handle(Authority::Managers(bob_name.clone()),
+                               alice_name.clone(),
+                               envelope,
+                               now + 60)
+                       .is_empty());
+        let info = unwrap_option!(manager.account_info(&alice_name), "");
+        assert_eq!(info.outbox().message_count(), 1);
+
+        // Recalling it again completes, since Bob's inbox no longer holds its header.
+        let recall = alice.sign(MpidMessageWrapper::Recall(name.clone()));
+        assert_eq!(send(&mut manager, &alice, &alice_name, recall),
+                   vec![(alice_name.clone(),
+                         MpidMessageWrapper::RecallResponse(name, RecallOutcome::Recalled))]);
+        assert!(manager.account_info(&alice_name).is_none());
+    }
+
+    #[test]
+    fn sealed_sender() {
+        let mut manager = MpidManager::new(60);
+        let mut alice = TestClient::new();
+        let mut bob = TestClient::new();
+        let alice_name = alice.name.clone();
+        let bob_name = bob.name.clone();
+        let (bob_public_key, bob_secret_key) = box_::gen_keypair();
+        let message = unwrap_result!(MpidMessage::new_with_sealed_sender(alice_name.clone(),
+                                                                         vec![],
+                                                                         bob_name.clone(),
+                                                                         vec![1, 2, 3],
+                                                                         vec![],
+                                                                         None,
+                                                                         &bob_public_key,
+                                                                         &alice.secret_key));
+        let header = message.header().clone();
+        let name = header_name(&header);
+
+        // Bob's MpidManagers can't tell from the header who sent it, but route his `GetMessage` to
+        // Alice's MpidManagers, which delivered it.
+        let online = bob.sign(MpidMessageWrapper::VersionedOnline(1, PROTOCOL_VERSION));
+        let _ = send(&mut manager, &bob, &bob_name, online);
+        let put = alice.sign(MpidMessageWrapper::PutMessage(message.clone()));
+        assert_eq!(send(&mut manager, &alice, &alice_name, put),
+                   vec![(bob_name.clone(), MpidMessageWrapper::PutHeader(header.clone()))]);
+        let get = bob.sign(MpidMessageWrapper::GetMessage(header.clone()));
+        assert_eq!(send(&mut manager, &bob, &bob_name, get),
+                   vec![(bob_name.clone(), MpidMessageWrapper::PutMessage(message.clone()))]);
+
+        // Bob opens the sender to delete the header from Alice's outbox.
+        assert!(header.verify_sealed_sender(&bob_secret_key, &alice.public_key));
+        let sender = unwrap_result!(header.open_sender(&bob_secret_key));
+        assert_eq!(sender, alice_name);
+        let delete = bob.sign(MpidMessageWrapper::DeleteHeader(name.clone()));
+        assert!(send(&mut manager, &bob, &sender, delete).is_empty());
+        assert!(manager.account_info(&alice_name).is_none());
+
+        // Once the header has been removed from Bob's inbox, there is no route to the message.
+        let delete = bob.sign(MpidMessageWrapper::DeleteMessage(name.clone()));
+        assert!(send(&mut manager, &bob, &bob_name, delete).is_empty());
+        let get = bob.sign(MpidMessageWrapper::GetMessage(header));
+        assert_eq!(send(&mut manager, &bob, &bob_name, get),
+                   vec![(bob_name.clone(),
+                         MpidMessageWrapper::GetMessageFailure(name, FailureReason::NotFound))]);
+    }
+
+    #[test]
+    fn multi() {
+        let mut manager = MpidManager::new(60);
+        let mut alice = TestClient::new();
+        let mut bob = TestClient::new();
+        let mut carol = TestClient::new();
+        let alice_name = alice.name.clone();
+        let message = unwrap_result!(MpidMultiMessage::new(alice_name.clone(),
+                                                           vec![],
+                                                           vec![bob.name.clone(),
+                                                                carol.name.clone()],
+                                                           ::generate_random_bytes(1000),
+                                                           vec![],
+                                                           None,
+                                                           &alice.secret_key));
+        let names = message.headers().into_iter().map(header_name).collect::<Vec<_>>();
+
+        // The message is stored once in Alice's outbox, with a header for each recipient.
+        let put = alice.sign(MpidMessageWrapper::PutMultiMessage(message.clone()));
+        assert!(send(&mut manager, &alice, &alice_name, put).is_empty());
+        let info = unwrap_option!(manager.account_info(&alice_name), "");
+        assert_eq!(info.outbox().message_count(), 2);
+        let size = info.outbox().bytes_used();
+        assert_eq!(size, unwrap_result!(::outbox::multi_message_size(&message)));
+        for client in &[&bob, &carol] {
+            let info = unwrap_option!(manager.account_info(&client.name), "");
+            assert_eq!(info.inbox().message_count(), 1);
+        }
+
+        // Each recipient retrieves and deletes its own message, the last freeing the body.
+        for (client, name) in vec![&mut bob, &mut carol].into_iter().zip(names.iter()) {
+            let expected = unwrap_option!(message.message_for(name), "");
+            let get = client.sign(MpidMessageWrapper::GetMessage(expected.header().clone()));
+            assert_eq!(send(&mut manager, client, &client.name.clone(), get),
+                       vec![(client.name.clone(), MpidMessageWrapper::PutMessage(expected))]);
+            let delete = client.sign(MpidMessageWrapper::DeleteHeader(name.clone()));
+            assert!(send(&mut manager, client, &alice_name, delete).is_empty());
+            let bytes_used = manager.account_info(&alice_name)
+                                    .map_or(0, |info| info.outbox().bytes_used());
+            assert!(bytes_used < size);
+        }
+        assert!(manager.account_info(&alice_name).is_none());
+
+        // A message with no recipients is rejected.
+        let mut empty = message.clone();
+        for name in &names {
+            assert!(empty.remove_recipient(name));
+        }
+        let put = alice.sign(MpidMessageWrapper::PutMultiMessage(empty));
+        assert_eq!(send(&mut manager, &alice, &alice_name, put),
+                   vec![(alice_name.clone(),
+                         MpidMessageWrapper::Failure(FailureReason::InvalidRecipients))]);
+    }
+
+    #[test]
+    fn unauthorised() {
+        let mut manager = MpidManager::new(60);
+        let mut alice = TestClient::new();
+        let bob = TestClient::new();
+        let alice_name = alice.name.clone();
+        let unauthorised = vec![(alice_name.clone(),
+                                 MpidMessageWrapper::Failure(FailureReason::Unauthorised))];
+
+        // Unsigned requests are rejected.
+        let unsigned = MpidEnvelope::new(1, MpidMessageWrapper::GetAccountInfo);
+        assert_eq!(send(&mut manager, &alice, &alice_name, unsigned), unauthorised);
+
+        // Requests acting on another Client's account are rejected.
+        let get_info = alice.sign(MpidMessageWrapper::GetAccountInfo);
+        assert_eq!(send(&mut manager, &alice, &bob.name, get_info), unauthorised);
+
+        // Replayed requests are rejected.
+        let get_info = alice.sign(MpidMessageWrapper::GetAccountInfo);
+        let responses = send(&mut manager, &alice, &alice_name, get_info.clone());
+        assert_eq!(responses.len(), 1);
+        let replayed = MpidMessageWrapper::Failure(FailureReason::Replayed);
+        assert_eq!(send(&mut manager, &alice, &alice_name, get_info),
+                   vec![(alice_name.clone(), replayed)]);
+
+        // Messages claiming to be from another sender are rejected.
+        let message = bob.new_message(&alice);
+        let name = header_name(message.header());
+        let put = alice.sign(MpidMessageWrapper::PutMessage(message));
+        assert_eq!(send(&mut manager, &alice, &alice_name, put),
+                   vec![(alice_name.clone(),
+                         MpidMessageWrapper::PutMessageFailure(name.clone(),
+                                                               FailureReason::InvalidSignature))]);
+
+        // Requests naming accounts which hold nothing don't create them.
+        let delete = alice.sign(MpidMessageWrapper::DeleteHeader(name.clone()));
+        assert_eq!(send(&mut manager, &alice, &bob.name, delete),
+                   vec![(alice_name.clone(),
+                         MpidMessageWrapper::DeleteHeaderFailure(name, FailureReason::NotFound))]);
+        assert!(manager.account_info(&alice_name).is_none());
+        assert!(manager.account_info(&bob.name).is_none());
+    }
+
+    #[test]
+    fn restart() {
+        let path = env::temp_dir().join(format!("mpid_manager_{:016x}.log", rand::random::<u64>()));
+        let mut alice = TestClient::new();
+        let mut bob = TestClient::new();
+        let alice_name = alice.name.clone();
+        let bob_name = bob.name.clone();
+
+        // Alice sends two messages to Bob, who retrieves the first.
+        let first = alice.new_message(&bob);
+        let second = alice.new_message(&bob);
+        let (alice_info, bob_info) = {
+            let store = Box::new(unwrap_result!(AppendLogStore::open(&path)));
+            let mut manager = unwrap_result!(MpidManager::with_store(store, 60));
+            for message in vec![first.clone(), second.clone()] {
+                let put = alice.sign(MpidMessageWrapper::PutMessage(message));
+                assert!(send(&mut manager, &alice, &alice_name, put).is_empty());
+            }
+            let get = bob.sign(MpidMessageWrapper::GetMessage(first.header().clone()));
+            let _ = send(&mut manager, &bob, &bob_name, get);
+            (unwrap_option!(manager.account_info(&alice_name), ""),
+             unwrap_option!(manager.account_info(&bob_name), ""))
+        };
+        assert_eq!(alice_info.outbox().message_count(), 2);
+        assert_eq!(bob_info.inbox().message_count(), 2);
+
+        // After restarting, both accounts are restored with the same usage, and the first message
+        // is still known to have been retrieved.
+        let store = Box::new(unwrap_result!(AppendLogStore::open(&path)));
+        let mut manager = unwrap_result!(MpidManager::with_store(store, 60));
+        assert_eq!(manager.account_info(&alice_name), Some(alice_info));
+        assert_eq!(manager.account_info(&bob_name), Some(bob_info));
+        let online = alice.sign(MpidMessageWrapper::VersionedOnline(1, PROTOCOL_VERSION));
+        let _ = send(&mut manager, &alice, &alice_name, online);
+        let name = header_name(first.header());
+        let recall = alice.sign(MpidMessageWrapper::Recall(name.clone()));
+        assert_eq!(send(&mut manager, &alice, &alice_name, recall),
+                   vec![(alice_name.clone(),
+                         MpidMessageWrapper::RecallResponse(name,
+                                                            RecallOutcome::AlreadyRetrieved))]);
+        let info = unwrap_option!(manager.account_info(&bob_name), "");
+        assert_eq!(info.inbox().message_count(), 1);
+
+        // Requests handled before the restart can't be replayed.
+        let get_info = alice.sign(MpidMessageWrapper::GetAccountInfo);
+        assert_eq!(send(&mut manager, &alice, &alice_name, get_info.clone()).len(), 1);
+        let store = Box::new(unwrap_result!(AppendLogStore::open(&path)));
+        let mut manager = unwrap_result!(MpidManager::with_store(store, 60));
+        let replayed = MpidMessageWrapper::Failure(FailureReason::Replayed);
+        assert_eq!(send(&mut manager, &alice, &alice_name, get_info),
+                   vec![(alice_name.clone(), replayed)]);
+        unwrap_result!(fs::remove_file(&path));
+    }
+
+    #[test]
+    fn storage_failure() {
+        let fail_nonces = Rc::new(Cell::new(false));
+        let store = Box::new(FailingStore {
+            store: MemoryStore::new(),
+            fail_nonces: fail_nonces.clone(),
+        });
+        let mut manager = unwrap_result!(MpidManager::with_store(store, 60));
+        let mut alice = TestClient::new();
+        let alice_name = alice.name.clone();
+        let online = alice.sign(MpidMessageWrapper::VersionedOnline(1, PROTOCOL_VERSION));
+        let _ = send(&mut manager, &alice, &alice_name, online);
+
+        // A request whose nonce can't be persisted fails without its nonce being accepted, so it
+        // can be retried.
+        let get_info = alice.sign(MpidMessageWrapper::GetAccountInfo);
+        fail_nonces.set(true);
+        let storage_failure = MpidMessageWrapper::Failure(FailureReason::StorageFailure);
+        assert_eq!(send(&mut manager, &alice, &alice_name, get_info.clone()),
+                   vec![(alice_name.clone(), storage_failure)]);
+        fail_nonces.set(false);
+        let responses = send(&mut manager, &alice, &alice_name, get_info.clone());
+        match responses.get(0) {
+            Some(&(_, MpidMessageWrapper::GetAccountInfoResponse(_))) => (),
+            response => panic!("Unexpected response: {:?}", response),
+        }
+        let replayed = MpidMessageWrapper::Failure(FailureReason::Replayed);
+        assert_eq!(send(&mut manager, &alice, &alice_name, get_info),
+                   vec![(alice_name.clone(), replayed)]);
+    }
+}
@@ -22,14 +22,18 @@ pub const MAX_BODY_SIZE: usize = 102400 - 512 - super::MAX_HEADER_METADATA_SIZE;
 use std::fmt::{self, Debug, Formatter};
 
 use maidsafe_utilities::serialisation::serialise;
+use sodiumoxide::crypto::{box_, secretbox};
 use sodiumoxide::crypto::sign::{self, PublicKey, SecretKey, Signature};
-use super::{Error, MpidHeader};
+use super::{Attachment, Error, MpidHeader};
+use super::{attachment, encryption};
 use xor_name::XorName;
 
 #[derive(PartialEq, Eq, Hash, Clone, RustcDecodable, RustcEncodable)]
 struct Detail {
     recipient: XorName,
     body: Vec<u8>,
+    attachments: Vec<Attachment>,
+    sealed_key: Option<Vec<u8>>,
 }
 
 /// A full message including header and body which can be sent to or retrieved from the network.
@@ -43,8 +47,8 @@ pub struct MpidMessage {
 impl MpidMessage {
     /// Constructor.
     ///
-    /// `sender` and `metadata` are used to construct an `MpidHeader` member, accessed via the
-    /// [`header()`](#method.header) getter.  For details on these arguments, see
+    /// `sender`, `metadata` and `time_to_live` are used to construct an `MpidHeader` member,
+    /// accessed via the [`header()`](#method.header) getter.  For details on these arguments, see
     /// [MpidHeader::new()](struct.MpidHeader.html#method.new).
     ///
     /// `recipient` represents the name of the intended receiver of the message.
@@ -52,31 +56,179 @@ impl MpidMessage {
     /// `body` is arbitrary, user-supplied data representing the main portion of the message.  It
     /// must not exceed [`MAX_BODY_SIZE`](constant.MAX_BODY_SIZE.html).  It can be empty if desired.
     ///
-    /// An error will be returned if `body` exceeds `MAX_BODY_SIZE`, if
+    /// `attachments` is a list of references to data stored elsewhere on the network.  It is
+    /// covered by the message signature, and the serialised attachments count towards
+    /// `MAX_BODY_SIZE`.  It can be empty if desired.
+    ///
+    /// An error will be returned if `body` and `attachments` exceed `MAX_BODY_SIZE`, if
     /// [MpidHeader::new()](struct.MpidHeader.html#method.new) fails or if
     /// serialisation during the signing process fails.
     pub fn new(sender: XorName,
                metadata: Vec<u8>,
                recipient: XorName,
                body: Vec<u8>,
+               attachments: Vec<Attachment>,
+               time_to_live: Option<u64>,
                secret_key: &SecretKey)
                -> Result<MpidMessage, Error> {
-        if body.len() > MAX_BODY_SIZE {
+        let content = try!(content(&body, &attachments, &None));
+        let header = try!(MpidHeader::new(sender,
+                                          metadata,
+                                          recipient.clone(),
+                                          &content,
+                                          time_to_live,
+                                          secret_key));
+        Self::create(header, recipient, body, attachments, None, secret_key)
+    }
+
+    /// Constructor for a message whose body can only be read by the recipient.
+    ///
+    /// The arguments are as per [`new()`](#method.new), except that `body` is encrypted using a
+    /// new random symmetric key, and that key is in turn encrypted to `recipient_public_key`.  Only
+    /// the holder of the corresponding secret key can retrieve the original body via
+    /// [`decrypt_body()`](#method.decrypt_body).  The signature covers the encrypted form, so
+    /// [`verify()`](#method.verify) can still be called by anyone.
+    ///
+    /// Each attachment's data map is encrypted using the same key as the body; the remaining
+    /// attachment fields are left visible.  See
+    /// [`decrypt_attachments()`](#method.decrypt_attachments).  `metadata` is left unencrypted; use
+    /// [`new_encrypted_with_sealed_metadata()`](#method.new_encrypted_with_sealed_metadata) to
+    /// encrypt it too.
+    ///
+    /// The encrypted form of `body` is slightly larger than the original, and it is this which must
+    /// not exceed [`MAX_BODY_SIZE`](constant.MAX_BODY_SIZE.html).
+    #[cfg_attr(feature="clippy", allow(too_many_arguments))]
+    pub fn new_encrypted(sender: XorName,
+                         metadata: Vec<u8>,
+                         recipient: XorName,
+                         body: Vec<u8>,
+                         attachments: Vec<Attachment>,
+                         time_to_live: Option<u64>,
+                         recipient_public_key: &box_::PublicKey,
+                         secret_key: &SecretKey)
+                         -> Result<MpidMessage, Error> {
+        let (key, sealed_key) = encryption::generate_sealed_key(recipient_public_key);
+        let encrypted_body = encryption::encrypt(&body, &key);
+        let attachments = attachment::encrypt_data_maps(attachments, &key);
+        let sealed_key = Some(sealed_key);
+        let content = try!(content(&encrypted_body, &attachments, &sealed_key));
+        let header = try!(MpidHeader::new(sender,
+                                          metadata,
+                                          recipient.clone(),
+                                          &content,
+                                          time_to_live,
+                                          secret_key));
+        Self::create(header,
+                     recipient,
+                     encrypted_body,
+                     attachments,
+                     sealed_key,
+                     secret_key)
+    }
+
+    /// Constructor for a message whose body and metadata can only be read by the recipient.
+    ///
+    /// As per [`new_encrypted()`](#method.new_encrypted), except that `metadata` is also encrypted
+    /// to `recipient_public_key`, and can be retrieved via
+    /// [`MpidHeader::open_metadata()`](struct.MpidHeader.html#method.open_metadata).  The
+    /// encrypted form of `metadata` is 72 bytes larger than the original, and it is this which must
+    /// not exceed [`MAX_HEADER_METADATA_SIZE`](constant.MAX_HEADER_METADATA_SIZE.html).
+    #[cfg_attr(feature="clippy", allow(too_many_arguments))]
+    pub fn new_encrypted_with_sealed_metadata(sender: XorName,
+                                              metadata: Vec<u8>,
+                                              recipient: XorName,
+                                              body: Vec<u8>,
+                                              attachments: Vec<Attachment>,
+                                              time_to_live: Option<u64>,
+                                              recipient_public_key: &box_::PublicKey,
+                                              secret_key: &SecretKey)
+                                              -> Result<MpidMessage, Error> {
+        let (key, sealed_key) = encryption::generate_sealed_key(recipient_public_key);
+        let encrypted_body = encryption::encrypt(&body, &key);
+        let attachments = attachment::encrypt_data_maps(attachments, &key);
+        let sealed_key = Some(sealed_key);
+        let content = try!(content(&encrypted_body, &attachments, &sealed_key));
+        let header = try!(MpidHeader::new_with_sealed_metadata(sender,
+                                                               metadata,
+                                                               recipient.clone(),
+                                                               &content,
+                                                               time_to_live,
+                                                               recipient_public_key,
+                                                               secret_key));
+        Self::create(header,
+                     recipient,
+                     encrypted_body,
+                     attachments,
+                     sealed_key,
+                     secret_key)
+    }
+
+    /// Constructor for an encrypted message whose sender can only be identified by the recipient.
+    ///
+    /// The arguments and encryption are as per
+    /// [`new_encrypted_with_sealed_metadata()`](#method.new_encrypted_with_sealed_metadata), but
+    /// the sender's name and the header signature are also encrypted to
+    /// `recipient_public_key`, so the recipient's MpidManagers can't tell from the header who sent
+    /// the message.  See [MpidHeader::open_sender()](struct.MpidHeader.html#method.open_sender).
+    #[cfg_attr(feature="clippy", allow(too_many_arguments))]
+    pub fn new_with_sealed_sender(sender: XorName,
+                                  metadata: Vec<u8>,
+                                  recipient: XorName,
+                                  body: Vec<u8>,
+                                  attachments: Vec<Attachment>,
+                                  time_to_live: Option<u64>,
+                                  recipient_public_key: &box_::PublicKey,
+                                  secret_key: &SecretKey)
+                                  -> Result<MpidMessage, Error> {
+        let (key, sealed_key) = encryption::generate_sealed_key(recipient_public_key);
+        let encrypted_body = encryption::encrypt(&body, &key);
+        let attachments = attachment::encrypt_data_maps(attachments, &key);
+        let sealed_key = Some(sealed_key);
+        let content = try!(content(&encrypted_body, &attachments, &sealed_key));
+        let header = try!(MpidHeader::new_with_sealed_sender(sender,
+                                                             metadata,
+                                                             recipient.clone(),
+                                                             &content,
+                                                             time_to_live,
+                                                             recipient_public_key,
+                                                             secret_key));
+        Self::create(header,
+                     recipient,
+                     encrypted_body,
+                     attachments,
+                     sealed_key,
+                     secret_key)
+    }
+
+    fn create(header: MpidHeader,
+              recipient: XorName,
+              body: Vec<u8>,
+              attachments: Vec<Attachment>,
+              sealed_key: Option<Vec<u8>>,
+              secret_key: &SecretKey)
+              -> Result<MpidMessage, Error> {
+        let mut size = body.len();
+        for attachment in &attachments {
+            size += try!(serialise(attachment)).len();
+        }
+        if size > MAX_BODY_SIZE {
             return Err(Error::BodyTooLarge);
         }
 
-        let header = try!(MpidHeader::new(sender, metadata, secret_key));
-
         let detail = Detail {
             recipient: recipient,
             body: body,
+            attachments: attachments,
+            sealed_key: sealed_key,
         };
 
-        let recipient_and_body = try!(serialise(&detail));
+        // The signature covers the header too, so that a sealed-sender header, whose own signature
+        // only the recipient can check, is still bound to the sender's key.
+        let signed_content = try!(serialise(&(&header, &detail)));
         Ok(MpidMessage {
+            signature: sign::sign_detached(&signed_content, secret_key),
             header: header,
             detail: detail,
-            signature: sign::sign_detached(&recipient_and_body, secret_key),
         })
     }
 
@@ -90,11 +242,46 @@ impl MpidMessage {
         &self.detail.recipient
     }
 
-    /// Arbitrary, user-supplied data representing the main portion of the message.
+    /// Arbitrary, user-supplied data representing the main portion of the message.  If the message
+    /// was constructed via [`new_encrypted()`](#method.new_encrypted), this is the encrypted form.
     pub fn body(&self) -> &Vec<u8> {
         &self.detail.body
     }
 
+    /// Whether the body is encrypted, i.e. whether the message was constructed via
+    /// [`new_encrypted()`](#method.new_encrypted).
+    pub fn is_encrypted(&self) -> bool {
+        self.detail.sealed_key.is_some()
+    }
+
+    /// Decrypts and returns the body of a message constructed via
+    /// [`new_encrypted()`](#method.new_encrypted).
+    ///
+    /// An error will be returned if the message isn't encrypted, or if `recipient_secret_key`
+    /// doesn't correspond to the public key used to encrypt the body.
+    pub fn decrypt_body(&self, recipient_secret_key: &box_::SecretKey) -> Result<Vec<u8>, Error> {
+        let key = try!(self.open_key(recipient_secret_key));
+        encryption::decrypt(&self.detail.body, &key)
+    }
+
+    /// References to data stored elsewhere on the network.  If the message was constructed via
+    /// [`new_encrypted()`](#method.new_encrypted), the data maps are in encrypted form.
+    pub fn attachments(&self) -> &Vec<Attachment> {
+        &self.detail.attachments
+    }
+
+    /// Returns the attachments of a message constructed via
+    /// [`new_encrypted()`](#method.new_encrypted), with their data maps decrypted.
+    ///
+    /// An error will be returned if the message isn't encrypted, or if `recipient_secret_key`
+    /// doesn't correspond to the public key used to encrypt the message.
+    pub fn decrypt_attachments(&self,
+                               recipient_secret_key: &box_::SecretKey)
+                               -> Result<Vec<Attachment>, Error> {
+        let key = try!(self.open_key(recipient_secret_key));
+        attachment::decrypt_data_maps(&self.detail.attachments, &key)
+    }
+
     /// The name of the message, equivalent to the
     /// [`MpidHeader::name()`](../struct.MpidHeader.html#method.name).  As per that getter, this is
     /// relatively expensive, so its use should be minimised.
@@ -102,25 +289,105 @@ impl MpidMessage {
         self.header.name()
     }
 
-    /// Validates the message and header signatures against the provided `PublicKey`.
+    /// Validates the message and header signatures against the provided `PublicKey`, and checks
+    /// that the header was created for this message's recipient, body, attachments and encryption
+    /// key (see [MpidHeader::matches_body()](struct.MpidHeader.html#method.matches_body)).
+    ///
+    /// For a message constructed via [`new_with_sealed_sender()`](#method.new_with_sealed_sender),
+    /// the header's own signature is only available to the recipient (see
+    /// [MpidHeader::verify_sealed_sender()](struct.MpidHeader.html#method.verify_sealed_sender)),
+    /// but the message signature covers the whole header, so the header is still validated as
+    /// having been created with the sender's key.
     pub fn verify(&self, public_key: &PublicKey) -> bool {
-        match serialise(&self.detail) {
-            Ok(recipient_and_body) => {
-                sign::verify_detached(&self.signature, &recipient_and_body, public_key) &&
-                self.header.verify(public_key)
+        let content = content(&self.detail.body,
+                              &self.detail.attachments,
+                              &self.detail.sealed_key);
+        match (serialise(&(&self.header, &self.detail)), content) {
+            (Ok(signed_content), Ok(content)) => {
+                self.header.matches_body(&self.detail.recipient, &content) &&
+                sign::verify_detached(&self.signature, &signed_content, public_key) &&
+                (self.header.is_sender_sealed() || self.header.verify(public_key))
             }
-            Err(_) => false,
+            _ => false,
         }
     }
+
+    fn open_key(&self, recipient_secret_key: &box_::SecretKey) -> Result<secretbox::Key, Error> {
+        match self.detail.sealed_key {
+            Some(ref sealed_key) => encryption::open_sealed_key(sealed_key, recipient_secret_key),
+            None => Err(Error::NotEncrypted),
+        }
+    }
+
+    /// Validates the message as per [`verify()`](#method.verify), and also checks that the header's
+    /// sender name is the one derived from `public_key`.  See
+    /// [MpidHeader::verify_sender()](struct.MpidHeader.html#method.verify_sender).
+    ///
+    /// This always fails for a message constructed via
+    /// [`new_with_sealed_sender()`](#method.new_with_sealed_sender), since the sender's name is
+    /// only available to the recipient.
+    pub fn verify_sender(&self, public_key: &PublicKey) -> bool {
+        self.verify(public_key) && self.header.verify_sender(public_key)
+    }
+}
+
+// The content to which a message's header commits via its body hash: the serialised body,
+// attachments and sealed key.  Any change to these invalidates the header.
+pub fn content(body: &[u8],
+               attachments: &[Attachment],
+               sealed_key: &Option<Vec<u8>>)
+               -> Result<Vec<u8>, Error> {
+    Ok(try!(serialise(&(body, attachments, sealed_key))))
+}
+
+// Constructs a message with the given header as per `MpidMessage::new()`.  Used by
+// `MpidMultiMessage` to sign the message for each of its recipients.
+pub fn create(header: MpidHeader,
+              recipient: XorName,
+              body: Vec<u8>,
+              attachments: Vec<Attachment>,
+              sealed_key: Option<Vec<u8>>,
+              secret_key: &SecretKey)
+              -> Result<MpidMessage, Error> {
+    MpidMessage::create(header, recipient, body, attachments, sealed_key, secret_key)
+}
+
+// Splits a message into its header, sealed key and signature, discarding the recipient, body and
+// attachments.
+pub fn into_parts(message: MpidMessage) -> (MpidHeader, Option<Vec<u8>>, Signature) {
+    (message.header, message.detail.sealed_key, message.signature)
+}
+
+// Reverses `into_parts()`.  The recipient is taken from the header.
+pub fn from_parts(header: MpidHeader,
+                  body: Vec<u8>,
+                  attachments: Vec<Attachment>,
+                  sealed_key: Option<Vec<u8>>,
+                  signature: Signature)
+                  -> MpidMessage {
+    let detail = Detail {
+        recipient: header.recipient().clone(),
+        body: body,
+        attachments: attachments,
+        sealed_key: sealed_key,
+    };
+    MpidMessage {
+        header: header,
+        detail: detail,
+        signature: signature,
+    }
 }
 
 impl Debug for MpidMessage {
     fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
         write!(formatter,
-               "MpidMessage {{ header: {:?}, recipient: {:?}, body: {}, signature: {} }}",
+               "MpidMessage {{ header: {:?}, recipient: {:?}, body: {}, attachments: {:?}, \
+                encrypted: {}, signature: {} }}",
                self.header,
                self.detail.recipient,
                ::format_binary_array(&self.detail.body),
+               self.detail.attachments,
+               self.is_encrypted(),
                ::format_binary_array(&self.signature))
     }
 }
@@ -129,7 +396,7 @@ impl Debug for MpidMessage {
 mod test {
     use super::*;
     use rand;
-    use sodiumoxide::crypto::sign;
+    use sodiumoxide::crypto::{box_, sign};
     use xor_name::XorName;
 
     #[test]
@@ -145,6 +412,8 @@ mod test {
                                                           metadata.clone(),
                                                           recipient.clone(),
                                                           vec![],
+                                                          vec![],
+                                                          None,
                                                           &secret_key));
             assert!(message.body().is_empty());
         }
@@ -153,6 +422,8 @@ mod test {
                                                       metadata.clone(),
                                                       recipient.clone(),
                                                       body.clone(),
+                                                      vec![],
+                                                      None,
                                                       &secret_key));
         assert!(*message.body() == body);
         body.push(0);
@@ -160,6 +431,8 @@ mod test {
                                  metadata.clone(),
                                  recipient.clone(),
                                  body.clone(),
+                                 vec![],
+                                 None,
                                  &secret_key)
                     .is_err());
         let _ = body.pop();
@@ -173,4 +446,276 @@ mod test {
         }
         assert!(!message.verify(&public_key));
     }
+
+    #[test]
+    fn encrypted() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let (recipient_public_key, recipient_secret_key) = box_::gen_keypair();
+        let (_, wrong_secret_key) = box_::gen_keypair();
+        let sender: XorName = rand::random();
+        let recipient: XorName = rand::random();
+        let body = ::generate_random_bytes(1000);
+
+        let metadata = ::generate_random_bytes(10);
+
+        let message = unwrap_result!(MpidMessage::new_encrypted(sender.clone(),
+                                                                metadata.clone(),
+                                                                recipient.clone(),
+                                                                body.clone(),
+                                                                vec![],
+                                                                None,
+                                                                &recipient_public_key,
+                                                                &secret_key));
+        assert!(message.is_encrypted());
+        assert!(*message.body() != body);
+        assert!(message.verify(&public_key));
+        assert_eq!(unwrap_result!(message.decrypt_body(&recipient_secret_key)), body);
+        assert!(message.decrypt_body(&wrong_secret_key).is_err());
+        assert!(!message.header().is_metadata_sealed());
+        assert_eq!(*message.header().metadata(), metadata);
+
+        // Metadata is only encrypted on request, and the encrypted form must fit within
+        // `MAX_HEADER_METADATA_SIZE`.
+        let message =
+            unwrap_result!(MpidMessage::new_encrypted_with_sealed_metadata(sender.clone(),
+                                                                           metadata.clone(),
+                                                                           recipient.clone(),
+                                                                           body.clone(),
+                                                                           vec![],
+                                                                           None,
+                                                                           &recipient_public_key,
+                                                                           &secret_key));
+        assert!(message.verify(&public_key));
+        assert_eq!(unwrap_result!(message.decrypt_body(&recipient_secret_key)), body);
+        assert!(message.header().is_metadata_sealed());
+        assert_eq!(unwrap_result!(message.header().open_metadata(&recipient_secret_key)),
+                   metadata);
+        let metadata = ::generate_random_bytes(::MAX_HEADER_METADATA_SIZE);
+        assert!(MpidMessage::new_encrypted(sender.clone(),
+                                           metadata.clone(),
+                                           recipient.clone(),
+                                           vec![],
+                                           vec![],
+                                           None,
+                                           &recipient_public_key,
+                                           &secret_key)
+                    .is_ok());
+        assert!(MpidMessage::new_encrypted_with_sealed_metadata(sender.clone(),
+                                                                metadata,
+                                                                recipient.clone(),
+                                                                vec![],
+                                                                vec![],
+                                                                None,
+                                                                &recipient_public_key,
+                                                                &secret_key)
+                    .is_err());
+
+        // The encryption overhead must fit within `MAX_BODY_SIZE`.
+        let body = ::generate_random_bytes(MAX_BODY_SIZE);
+        assert!(MpidMessage::new_encrypted(sender.clone(),
+                                           vec![],
+                                           recipient.clone(),
+                                           body,
+                                           vec![],
+                                           None,
+                                           &recipient_public_key,
+                                           &secret_key)
+                    .is_err());
+
+        // A plain message can't be decrypted.
+        let message = unwrap_result!(MpidMessage::new(sender,
+                                                      vec![],
+                                                      recipient,
+                                                      vec![],
+                                                      vec![],
+                                                      None,
+                                                      &secret_key));
+        assert!(!message.is_encrypted());
+        assert!(message.decrypt_body(&recipient_secret_key).is_err());
+    }
+
+    #[test]
+    fn sealed_sender() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let (recipient_public_key, recipient_secret_key) = box_::gen_keypair();
+        let sender = ::name_from_public_key(&public_key);
+        let recipient: XorName = rand::random();
+        let body = ::generate_random_bytes(1000);
+
+        let message = unwrap_result!(MpidMessage::new_with_sealed_sender(sender.clone(),
+                                                                         vec![],
+                                                                         recipient.clone(),
+                                                                         body.clone(),
+                                                                         vec![],
+                                                                         None,
+                                                                         &recipient_public_key,
+                                                                         &secret_key));
+        assert!(message.is_encrypted());
+        assert!(message.header().sender().is_none());
+        assert!(message.verify(&public_key));
+        assert!(!message.verify_sender(&public_key));
+        assert!(message.header().verify_sealed_sender(&recipient_secret_key, &public_key));
+        assert_eq!(unwrap_result!(message.header().open_sender(&recipient_secret_key)),
+                   sender);
+        assert_eq!(unwrap_result!(message.decrypt_body(&recipient_secret_key)), body);
+
+        // The message signature covers the sealed header, so it can't be swapped for one sealed by
+        // someone else, even with a matching body hash.
+        let (other_public_key, other_secret_key) = sign::gen_keypair();
+        let other_sender = ::name_from_public_key(&other_public_key);
+        let content = unwrap_result!(content(message.body(),
+                                             message.attachments(),
+                                             &message.detail.sealed_key));
+        let forged_header = unwrap_result!(MpidHeader::new_with_sealed_sender(other_sender,
+                                                                             vec![],
+                                                                             recipient,
+                                                                             &content,
+                                                                             None,
+                                                                             &recipient_public_key,
+                                                                             &other_secret_key));
+        let forged = from_parts(forged_header,
+                                message.body().clone(),
+                                message.attachments().clone(),
+                                message.detail.sealed_key.clone(),
+                                message.signature);
+        assert!(!forged.verify(&public_key));
+        assert!(!forged.verify(&other_public_key));
+    }
+
+    #[test]
+    fn verify_sender() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let (other_public_key, _) = sign::gen_keypair();
+        let sender = ::name_from_public_key(&public_key);
+        let recipient: XorName = rand::random();
+
+        let message = unwrap_result!(MpidMessage::new(sender,
+                                                      vec![],
+                                                      recipient.clone(),
+                                                      vec![],
+                                                      vec![],
+                                                      None,
+                                                      &secret_key));
+        assert!(message.verify_sender(&public_key));
+        assert!(!message.verify_sender(&other_public_key));
+
+        let forged_sender = ::name_from_public_key(&other_public_key);
+        let message = unwrap_result!(MpidMessage::new(forged_sender,
+                                                      vec![],
+                                                      recipient,
+                                                      vec![],
+                                                      vec![],
+                                                      None,
+                                                      &secret_key));
+        assert!(message.verify(&public_key));
+        assert!(!message.verify_sender(&public_key));
+        assert!(!message.verify_sender(&other_public_key));
+    }
+
+    #[test]
+    fn header_bound_to_body() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let recipient: XorName = rand::random();
+
+        let message1 = unwrap_result!(MpidMessage::new(sender.clone(),
+                                                       vec![],
+                                                       recipient.clone(),
+                                                       ::generate_random_bytes(10),
+                                                       vec![],
+                                                       None,
+                                                       &secret_key));
+        let message2 = unwrap_result!(MpidMessage::new(sender,
+                                                       vec![],
+                                                       recipient.clone(),
+                                                       ::generate_random_bytes(10),
+                                                       vec![],
+                                                       None,
+                                                       &secret_key));
+        assert!(message1.verify(&public_key));
+        assert!(message2.verify(&public_key));
+        let content = unwrap_result!(content(message1.body(), message1.attachments(), &None));
+        assert!(message1.header().matches_body(&recipient, &content));
+        assert!(!message1.header().matches_body(&recipient, message1.body()));
+
+        // Both headers and both bodies are validly signed, but splicing them together must fail.
+        let mut spliced = message1.clone();
+        spliced.header = message2.header().clone();
+        assert!(!spliced.verify(&public_key));
+
+        // The same applies to messages which differ only in their attachments.
+        let body = ::generate_random_bytes(10);
+        let attachment = Attachment::from_content(rand::random(), &[1, 2, 3], None);
+        let message1 = unwrap_result!(MpidMessage::new(rand::random(),
+                                                       vec![],
+                                                       recipient.clone(),
+                                                       body.clone(),
+                                                       vec![],
+                                                       None,
+                                                       &secret_key));
+        let message2 = unwrap_result!(MpidMessage::new(rand::random(),
+                                                       vec![],
+                                                       recipient,
+                                                       body,
+                                                       vec![attachment],
+                                                       None,
+                                                       &secret_key));
+        let mut spliced = message2.clone();
+        spliced.header = message1.header().clone();
+        assert!(!spliced.verify(&public_key));
+    }
+
+    #[test]
+    fn attachments() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let (recipient_public_key, recipient_secret_key) = box_::gen_keypair();
+        let sender: XorName = rand::random();
+        let recipient: XorName = rand::random();
+        let body = ::generate_random_bytes(100);
+        let content = ::generate_random_bytes(1000);
+        let data_map = ::generate_random_bytes(50);
+        let attachment = Attachment::from_content(rand::random(), &content, Some(data_map.clone()));
+
+        // Plain message carries the attachment unchanged and signs over it.
+        let message = unwrap_result!(MpidMessage::new(sender.clone(),
+                                                      vec![],
+                                                      recipient.clone(),
+                                                      body.clone(),
+                                                      vec![attachment.clone()],
+                                                      None,
+                                                      &secret_key));
+        assert_eq!(*message.attachments(), vec![attachment.clone()]);
+        assert!(message.attachments()[0].verify_content(&content));
+        assert!(message.decrypt_attachments(&recipient_secret_key).is_err());
+        assert!(message.verify(&public_key));
+        let mut tampered = message.clone();
+        tampered.detail.attachments.clear();
+        assert!(!tampered.verify(&public_key));
+
+        // Encrypted message hides the data map until decrypted by the recipient.
+        let message = unwrap_result!(MpidMessage::new_encrypted(sender.clone(),
+                                                                vec![],
+                                                                recipient.clone(),
+                                                                body.clone(),
+                                                                vec![attachment.clone()],
+                                                                None,
+                                                                &recipient_public_key,
+                                                                &secret_key));
+        assert!(message.verify(&public_key));
+        assert!(message.attachments()[0].data_map() != Some(&data_map));
+        assert_eq!(message.attachments()[0].content_hash(), attachment.content_hash());
+        assert_eq!(unwrap_result!(message.decrypt_attachments(&recipient_secret_key)),
+                   vec![attachment.clone()]);
+
+        // Attachments count towards the size limit.
+        let body = ::generate_random_bytes(MAX_BODY_SIZE);
+        assert!(MpidMessage::new(sender,
+                                 vec![],
+                                 recipient,
+                                 body,
+                                 vec![attachment],
+                                 None,
+                                 &secret_key)
+                    .is_err());
+    }
 }
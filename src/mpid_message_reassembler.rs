@@ -0,0 +1,434 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+/// Maximum allowed length for a payload split via [`split_payload()`](fn.split_payload.html)
+/// (103,677,952 bytes, i.e. 1,024 parts each just under
+/// [`MAX_BODY_SIZE`](constant.MAX_BODY_SIZE.html)).
+pub const MAX_PAYLOAD_SIZE: usize = MAX_PARTS as usize * MAX_PART_DATA_SIZE;
+
+use std::collections::{BTreeMap, HashMap};
+
+use maidsafe_utilities::serialisation::{deserialise, serialise};
+use rand::{self, Rng};
+use sodiumoxide::crypto::box_;
+use sodiumoxide::crypto::hash::sha512;
+use sodiumoxide::crypto::sign::{PublicKey, SecretKey};
+use super::{Error, MpidMessage, GUID_SIZE, MAX_BODY_SIZE};
+use xor_name::XorName;
+
+// Maximum amount of the payload carried by each part.  This leaves room within `MAX_BODY_SIZE` for
+// the serialised `Part` fields and the encryption overhead of `MpidMessage::new_encrypted()`.
+const MAX_PART_DATA_SIZE: usize = MAX_BODY_SIZE - 512;
+// Maximum number of parts into which a payload can be split.
+const MAX_PARTS: u32 = 1024;
+
+// The body of each part-message.
+#[derive(RustcDecodable, RustcEncodable)]
+struct Part {
+    message_id: [u8; GUID_SIZE],
+    index: u32,
+    count: u32,
+    payload_hash: XorName,
+    data: Vec<u8>,
+}
+
+/// Splits `payload`, which may exceed [`MAX_BODY_SIZE`](constant.MAX_BODY_SIZE.html), into an
+/// ordered set of messages which can be sent individually and recombined by the recipient using an
+/// [`MpidMessageReassembler`](struct.MpidMessageReassembler.html).
+///
+/// The remaining arguments are as per [MpidMessage::new()](struct.MpidMessage.html#method.new).
+/// Every part carries a randomly-generated message ID shared by all parts, its index, the total
+/// number of parts and the hash of the whole payload, all of which are covered by the part's
+/// signature.
+///
+/// An error will be returned if `payload` exceeds
+/// [`MAX_PAYLOAD_SIZE`](constant.MAX_PAYLOAD_SIZE.html).
+pub fn split_payload(sender: XorName,
+                     metadata: Vec<u8>,
+                     recipient: XorName,
+                     payload: &[u8],
+                     time_to_live: Option<u64>,
+                     secret_key: &SecretKey)
+                     -> Result<Vec<MpidMessage>, Error> {
+    let mut messages = Vec::new();
+    for body in try!(part_bodies(payload)) {
+        messages.push(try!(MpidMessage::new(sender.clone(),
+                                            metadata.clone(),
+                                            recipient.clone(),
+                                            body,
+                                            vec![],
+                                            time_to_live,
+                                            secret_key)));
+    }
+    Ok(messages)
+}
+
+/// As per [`split_payload()`](fn.split_payload.html), except that each part is constructed via
+/// [MpidMessage::new_encrypted()](struct.MpidMessage.html#method.new_encrypted).
+pub fn split_encrypted_payload(sender: XorName,
+                               metadata: Vec<u8>,
+                               recipient: XorName,
+                               payload: &[u8],
+                               time_to_live: Option<u64>,
+                               recipient_public_key: &box_::PublicKey,
+                               secret_key: &SecretKey)
+                               -> Result<Vec<MpidMessage>, Error> {
+    let mut messages = Vec::new();
+    for body in try!(part_bodies(payload)) {
+        messages.push(try!(MpidMessage::new_encrypted(sender.clone(),
+                                                      metadata.clone(),
+                                                      recipient.clone(),
+                                                      body,
+                                                      vec![],
+                                                      time_to_live,
+                                                      recipient_public_key,
+                                                      secret_key)));
+    }
+    Ok(messages)
+}
+
+fn part_bodies(payload: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+    if payload.len() > MAX_PAYLOAD_SIZE {
+        return Err(Error::BodyTooLarge);
+    }
+    let mut message_id = [0u8; GUID_SIZE];
+    rand::thread_rng().fill_bytes(&mut message_id);
+    let payload_hash = XorName(sha512::hash(payload).0);
+    let chunks: Vec<&[u8]> = if payload.is_empty() {
+        vec![payload]
+    } else {
+        payload.chunks(MAX_PART_DATA_SIZE).collect()
+    };
+    let count = chunks.len() as u32;
+    let mut bodies = Vec::with_capacity(chunks.len());
+    for (index, chunk) in chunks.into_iter().enumerate() {
+        let part = Part {
+            message_id: message_id,
+            index: index as u32,
+            count: count,
+            payload_hash: payload_hash.clone(),
+            data: chunk.to_vec(),
+        };
+        bodies.push(try!(serialise(&part)));
+    }
+    Ok(bodies)
+}
+
+struct PendingPayload {
+    count: u32,
+    payload_hash: XorName,
+    parts: BTreeMap<u32, Vec<u8>>,
+}
+
+/// Collects the parts of payloads split via [`split_payload()`](fn.split_payload.html) or
+/// [`split_encrypted_payload()`](fn.split_encrypted_payload.html) and yields each payload once all
+/// of its parts have arrived.
+///
+/// Parts can be added in any order, and parts of several different payloads can be interleaved.
+/// Each payload is identified by its sender's public key along with its message ID, so parts from
+/// one sender can never be mixed into another's payload.
+pub struct MpidMessageReassembler {
+    pending: HashMap<(PublicKey, [u8; GUID_SIZE]), PendingPayload>,
+}
+
+impl MpidMessageReassembler {
+    /// Constructor.
+    pub fn new() -> MpidMessageReassembler {
+        MpidMessageReassembler { pending: HashMap::new() }
+    }
+
+    /// Adds a part created via [`split_payload()`](fn.split_payload.html).
+    ///
+    /// The part is validated against `sender_public_key`.  If it completes its payload, the
+    /// payload is checked against the hash carried in the parts and returned; otherwise `None` is
+    /// returned.
+    ///
+    /// `Error::InvalidPart` is returned if the message isn't a validly-signed part, claims more
+    /// parts than a payload of [`MAX_PAYLOAD_SIZE`](constant.MAX_PAYLOAD_SIZE.html) needs, or is
+    /// inconsistent with previously-added parts of the same payload, and `Error::DuplicatePart` if
+    /// the part has already been added.  `Error::InvalidPart` is also returned if the completed
+    /// payload doesn't match its hash; the parts are then retained until
+    /// [`discard()`](#method.discard) is called.
+    pub fn add_part(&mut self,
+                    message: &MpidMessage,
+                    sender_public_key: &PublicKey)
+                    -> Result<Option<Vec<u8>>, Error> {
+        if !message.verify(sender_public_key) {
+            return Err(Error::InvalidPart);
+        }
+        let part = try!(deserialise::<Part>(message.body()).map_err(|_| Error::InvalidPart));
+        self.insert(sender_public_key, part)
+    }
+
+    /// As per [`add_part()`](#method.add_part), but for a part created via
+    /// [`split_encrypted_payload()`](fn.split_encrypted_payload.html).  The part is decrypted using
+    /// `recipient_secret_key`.
+    pub fn add_encrypted_part(&mut self,
+                              message: &MpidMessage,
+                              sender_public_key: &PublicKey,
+                              recipient_secret_key: &box_::SecretKey)
+                              -> Result<Option<Vec<u8>>, Error> {
+        if !message.verify(sender_public_key) {
+            return Err(Error::InvalidPart);
+        }
+        let body = try!(message.decrypt_body(recipient_secret_key));
+        let part = try!(deserialise::<Part>(&body).map_err(|_| Error::InvalidPart));
+        self.insert(sender_public_key, part)
+    }
+
+    /// Returns the sender's public key, the ID and the indices of the missing parts of each
+    /// incomplete payload.
+    pub fn missing_parts(&self) -> Vec<(PublicKey, [u8; GUID_SIZE], Vec<u32>)> {
+        self.pending
+            .iter()
+            .map(|(&(sender_public_key, message_id), pending)| {
+                let missing = (0..pending.count)
+                                  .filter(|index| !pending.parts.contains_key(index))
+                                  .collect();
+                (sender_public_key, message_id, missing)
+            })
+            .collect()
+    }
+
+    /// Discards all parts received so far for the payload with the given sender and ID.  Returns
+    /// `false` if there were none.
+    pub fn discard(&mut self,
+                   sender_public_key: &PublicKey,
+                   message_id: &[u8; GUID_SIZE])
+                   -> bool {
+        self.pending.remove(&(*sender_public_key, *message_id)).is_some()
+    }
+
+    fn insert(&mut self,
+              sender_public_key: &PublicKey,
+              part: Part)
+              -> Result<Option<Vec<u8>>, Error> {
+        if part.count == 0 || part.count > MAX_PARTS || part.index >= part.count ||
+           part.data.len() > MAX_PART_DATA_SIZE {
+            return Err(Error::InvalidPart);
+        }
+        let key = (*sender_public_key, part.message_id);
+        let payload = {
+            let pending = self.pending.entry(key).or_insert_with(|| {
+                PendingPayload {
+                    count: part.count,
+                    payload_hash: part.payload_hash.clone(),
+                    parts: BTreeMap::new(),
+                }
+            });
+            if pending.count != part.count || pending.payload_hash != part.payload_hash {
+                return Err(Error::InvalidPart);
+            }
+            if pending.parts.contains_key(&part.index) {
+                return Err(Error::DuplicatePart);
+            }
+            let _ = pending.parts.insert(part.index, part.data);
+            if pending.parts.len() != pending.count as usize {
+                return Ok(None);
+            }
+
+            let mut payload = Vec::new();
+            for data in pending.parts.values() {
+                payload.extend_from_slice(data);
+            }
+            if XorName(sha512::hash(&payload).0) != pending.payload_hash {
+                return Err(Error::InvalidPart);
+            }
+            payload
+        };
+        let _ = self.pending.remove(&key);
+        Ok(Some(payload))
+    }
+}
+
+impl Default for MpidMessageReassembler {
+    fn default() -> MpidMessageReassembler {
+        MpidMessageReassembler::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::{MAX_PARTS, MAX_PART_DATA_SIZE, Part};
+    use maidsafe_utilities::serialisation::serialise;
+    use rand::{self, Rng};
+    use sodiumoxide::crypto::{box_, sign};
+    use sodiumoxide::crypto::hash::sha512;
+    use sodiumoxide::crypto::sign::SecretKey;
+    use xor_name::XorName;
+    use {Error, MpidMessage, GUID_SIZE, MAX_BODY_SIZE};
+
+    fn part_message(part: &Part, secret_key: &SecretKey) -> MpidMessage {
+        unwrap_result!(MpidMessage::new(rand::random(),
+                                        vec![],
+                                        rand::random(),
+                                        unwrap_result!(serialise(part)),
+                                        vec![],
+                                        None,
+                                        secret_key))
+    }
+
+    #[test]
+    fn full() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let (other_public_key, _) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let recipient: XorName = rand::random();
+        let payload = ::generate_random_bytes(3 * MAX_BODY_SIZE);
+
+        let mut parts = unwrap_result!(split_payload(sender.clone(),
+                                                     vec![],
+                                                     recipient.clone(),
+                                                     &payload,
+                                                     None,
+                                                     &secret_key));
+        assert_eq!(parts.len(), (payload.len() + MAX_PART_DATA_SIZE - 1) / MAX_PART_DATA_SIZE);
+        assert!(parts.iter().all(|part| part.body().len() <= MAX_BODY_SIZE));
+        rand::thread_rng().shuffle(&mut parts);
+
+        let mut reassembler = MpidMessageReassembler::new();
+        assert!(reassembler.add_part(&parts[0], &other_public_key).is_err());
+        let last = parts.pop().expect("Parts can't be empty");
+        for part in &parts {
+            assert!(unwrap_result!(reassembler.add_part(part, &public_key)).is_none());
+        }
+        match reassembler.add_part(&parts[0], &public_key) {
+            Err(Error::DuplicatePart) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        let missing = reassembler.missing_parts();
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0].0, public_key);
+        assert_eq!(missing[0].2.len(), 1);
+
+        let reassembled = unwrap_option!(unwrap_result!(reassembler.add_part(&last, &public_key)),
+                                         "");
+        assert_eq!(reassembled, payload);
+        assert!(reassembler.missing_parts().is_empty());
+
+        // A message which isn't a part is rejected.
+        let message = unwrap_result!(::MpidMessage::new(sender,
+                                                        vec![],
+                                                        recipient,
+                                                        vec![1, 2, 3],
+                                                        vec![],
+                                                        None,
+                                                        &secret_key));
+        assert!(reassembler.add_part(&message, &public_key).is_err());
+    }
+
+    #[test]
+    fn encrypted() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let (recipient_public_key, recipient_secret_key) = box_::gen_keypair();
+        let sender: XorName = rand::random();
+        let recipient: XorName = rand::random();
+        let payload = ::generate_random_bytes(MAX_BODY_SIZE + 1);
+
+        let parts = unwrap_result!(split_encrypted_payload(sender,
+                                                           vec![],
+                                                           recipient,
+                                                           &payload,
+                                                           None,
+                                                           &recipient_public_key,
+                                                           &secret_key));
+        assert_eq!(parts.len(), 2);
+        assert!(parts.iter().all(|part| part.is_encrypted()));
+
+        let mut reassembler = MpidMessageReassembler::new();
+        assert!(unwrap_result!(reassembler.add_encrypted_part(&parts[1],
+                                                              &public_key,
+                                                              &recipient_secret_key))
+                    .is_none());
+        assert!(reassembler.add_part(&parts[0], &public_key).is_err());
+        let reassembled = unwrap_result!(reassembler.add_encrypted_part(&parts[0],
+                                                                        &public_key,
+                                                                        &recipient_secret_key));
+        assert_eq!(unwrap_option!(reassembled, ""), payload);
+    }
+
+    #[test]
+    fn empty_payload() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let parts = unwrap_result!(split_payload(rand::random(),
+                                                 vec![],
+                                                 rand::random(),
+                                                 &[],
+                                                 None,
+                                                 &secret_key));
+        assert_eq!(parts.len(), 1);
+        let mut reassembler = MpidMessageReassembler::new();
+        let reassembled = unwrap_result!(reassembler.add_part(&parts[0], &public_key));
+        assert_eq!(unwrap_option!(reassembled, ""), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn invalid_parts() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let (other_public_key, other_secret_key) = sign::gen_keypair();
+        let message_id = [1u8; GUID_SIZE];
+        let data = vec![1, 2, 3];
+        let new_part = |index, count, payload_hash: &XorName| {
+            Part {
+                message_id: message_id,
+                index: index,
+                count: count,
+                payload_hash: payload_hash.clone(),
+                data: data.clone(),
+            }
+        };
+        let mut payload = data.clone();
+        payload.extend_from_slice(&data);
+        let payload_hash = XorName(sha512::hash(&payload).0);
+        let mut reassembler = MpidMessageReassembler::new();
+
+        // A part from another sender with the same message ID is kept separate.
+        let part = part_message(&new_part(0, 2, &payload_hash), &secret_key);
+        assert!(unwrap_result!(reassembler.add_part(&part, &public_key)).is_none());
+        let other_part = part_message(&new_part(1, 2, &payload_hash), &other_secret_key);
+        assert!(unwrap_result!(reassembler.add_part(&other_part, &other_public_key)).is_none());
+        assert_eq!(reassembler.missing_parts().len(), 2);
+        let part = part_message(&new_part(1, 2, &payload_hash), &secret_key);
+        let reassembled = unwrap_result!(reassembler.add_part(&part, &public_key));
+        assert_eq!(unwrap_option!(reassembled, ""), payload);
+        assert!(reassembler.discard(&other_public_key, &message_id));
+        assert!(reassembler.missing_parts().is_empty());
+
+        // A part claiming more parts than the maximum payload needs is rejected.
+        for count in &[MAX_PARTS + 1, u32::max_value()] {
+            let part = part_message(&new_part(0, *count, &payload_hash), &secret_key);
+            match reassembler.add_part(&part, &public_key) {
+                Err(Error::InvalidPart) => (),
+                result => panic!("Unexpected result: {:?}", result),
+            }
+        }
+        assert!(reassembler.missing_parts().is_empty());
+
+        // A payload which doesn't match its hash is reported, but its parts are retained.
+        let wrong_hash: XorName = rand::random();
+        let part = part_message(&new_part(0, 1, &wrong_hash), &secret_key);
+        match reassembler.add_part(&part, &public_key) {
+            Err(Error::InvalidPart) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        assert_eq!(reassembler.missing_parts(),
+                   vec![(public_key, message_id, Vec::<u32>::new())]);
+        assert!(reassembler.discard(&public_key, &message_id));
+        assert!(!reassembler.discard(&public_key, &message_id));
+    }
+}
@@ -18,15 +18,27 @@
 // use maidsafe_utilities::serialisation::serialise;
 // use sodiumoxide::crypto::hash::sha512;
 // use sodiumoxide::crypto::sign::{self, PublicKey, SecretKey, Signature};
-use super::{MpidHeader, MpidMessage};
+use std::cmp;
+
+use super::{AccountInfo, Error, FailureReason, HeaderPage, MpidHeader, MpidMessage,
+            MpidMultiMessage, MpidReceipt, PageRequest, SignedRequest};
 use xor_name::XorName;
 
 /// A serialisable wrapper to allow multiplexing all MPID message types and actions via a single
 /// type.
+///
+/// New variants must only ever be appended, so that peers running older versions of the protocol
+/// can still decode the existing ones, and each addition must increment
+/// [`PROTOCOL_VERSION`](constant.PROTOCOL_VERSION.html) and be reflected in
+/// [`min_version()`](#method.min_version).  The variants up to and including `DeleteHeader`
+/// predate versioning, and must keep their original form.
 #[derive(PartialEq, Eq, Hash, Clone, Debug, RustcDecodable, RustcEncodable)]
 pub enum MpidMessageWrapper {
     /// Sent by a Client to its MpidManagers to notify them that it has just connected to the
     /// network.
+    ///
+    /// This predates version negotiation, so is treated as offering only version 1.  Clients
+    /// should send `VersionedOnline` instead.
     Online,
     /// Sent by a Client to its MpidManagers when storing a new `MpidMessage`.
     PutMessage(MpidMessage),
@@ -43,7 +55,7 @@ pub enum MpidMessageWrapper {
     /// subset of the list provided in the corresponding `OutboxHas`.
     OutboxHasResponse(Vec<MpidHeader>),
     /// Sent by a Client to its MpidManagers to retrieve the list of headers of all messages in its
-    /// outbox.
+    /// outbox.  `GetOutboxHeadersPage` should be preferred, since the list may be large.
     GetOutboxHeaders,
     /// Sent by MpidManagers to the Client as a response to a `GetOutboxHeaders`.  The contents is
     /// the list of headers of all messages in the outbox.
@@ -53,4 +65,262 @@ pub enum MpidMessageWrapper {
     /// Sent by a receiving Client to the sender's MpidManagers to delete the named message's header
     /// from the sender's outbox.
     DeleteHeader(XorName),
+    /// Sent by a Client to its MpidManagers when storing a new `MpidMultiMessage`.  The
+    /// MpidManagers send a `PutHeader` to each recipient's MpidManagers.
+    PutMultiMessage(MpidMultiMessage),
+    /// Sent by a Client to its MpidManagers to retrieve a page of the headers of the messages in
+    /// its outbox.
+    GetOutboxHeadersPage(PageRequest),
+    /// Sent by MpidManagers to the Client as a response to a `GetOutboxHeadersPage`.  The contents
+    /// is the requested page of headers of the messages in the outbox.
+    GetOutboxHeadersPageResponse(HeaderPage),
+    /// Sent by a Client to its MpidManagers to query whether the provided vector of message names
+    /// continue to exist as headers in its inbox.
+    InboxHas(Vec<XorName>),
+    /// Sent by MpidManagers to the Client as a response to an `InboxHas`.  The contents is a
+    /// subset of the list provided in the corresponding `InboxHas`.
+    InboxHasResponse(Vec<MpidHeader>),
+    /// Sent by a Client to its MpidManagers to retrieve a page of the headers of the messages in
+    /// its inbox, e.g. to resynchronise after sending `VersionedOnline`.
+    GetInboxHeaders(PageRequest),
+    /// Sent by MpidManagers to the Client as a response to a `GetInboxHeaders`.  The contents is
+    /// the requested page of headers of the messages in the inbox.
+    GetInboxHeadersResponse(HeaderPage),
+    /// Sent by a receiving Client to the sender's MpidManagers to acknowledge delivery or reading
+    /// of a message.  The MpidManagers forward it to the sender.
+    PutReceipt(MpidReceipt),
+    /// Sent by MpidManagers to the Client when a `PutMessage` or `PutMultiMessage` fails.  The
+    /// contents is the name of the affected header (one response per header for a
+    /// `PutMultiMessage`) and the reason for the failure.
+    PutMessageFailure(XorName, FailureReason),
+    /// Sent by the receiver's MpidManagers to the sender's MpidManagers when a `PutHeader` fails,
+    /// e.g. because the receiver's inbox is full.
+    PutHeaderFailure(XorName, FailureReason),
+    /// Sent by MpidManagers to the receiver when a `GetMessage` fails, e.g. because the message no
+    /// longer exists.
+    GetMessageFailure(XorName, FailureReason),
+    /// Sent by MpidManagers to the Client when a `DeleteMessage` fails.
+    DeleteMessageFailure(XorName, FailureReason),
+    /// Sent by MpidManagers to the receiver when a `DeleteHeader` fails.
+    DeleteHeaderFailure(XorName, FailureReason),
+    /// Sent by MpidManagers to the Client when any other request fails, e.g. because the Client
+    /// isn't authorised to make it.
+    Failure(FailureReason),
+    /// Sent by a Client to its MpidManagers, in place of `Online`, to notify them that it has just
+    /// connected to the network.  The contents is the range of protocol versions supported by the
+    /// Client, as per [`negotiate_version()`](fn.negotiate_version.html).  The Client should follow
+    /// this with a `GetInboxHeaders` to retrieve the headers which arrived while it was offline.
+    VersionedOnline(u32, u32),
+    /// Sent by MpidManagers to the Client as a response to a `VersionedOnline`.  The contents is
+    /// the negotiated protocol version.  If negotiation fails, a `Failure(UnsupportedVersion)` is
+    /// sent instead.
+    OnlineResponse(u32),
+    /// Sent by a Client to its MpidManagers, or to another Client's MpidManagers, wrapping any
+    /// request which acts on an account, e.g. `DeleteMessage`, `DeleteHeader` or
+    /// `GetOutboxHeadersPage`.  MpidManagers should authenticate it via a
+    /// [`RequestVerifier`](struct.RequestVerifier.html) and reject such requests otherwise.
+    Signed(SignedRequest),
+    /// Sent by a Client, wrapped in a `Signed`, to its MpidManagers to recall the named message
+    /// from its outbox.  The MpidManagers forward the signed request to the recipient's
+    /// MpidManagers in a `RecallHeader`, and only remove the message and respond once those reply
+    /// with a `RecallHeaderResponse`.
+    Recall(XorName),
+    /// Sent by the sender's MpidManagers to the receiver's MpidManagers to drop the header of a
+    /// recalled message from the receiver's inbox.  The contents is the sender's signed `Recall`,
+    /// which should be checked via
+    /// [`SignedRequest::authorises_recall()`](struct.SignedRequest.html#method.authorises_recall)
+    /// against the account whose MpidManagers delivered the header.
+    RecallHeader(SignedRequest),
+    /// Sent by MpidManagers to the Client as a response to a `Recall`.
+    RecallResponse(XorName, RecallOutcome),
+    /// Sent by MpidManagers to the Client when a `Recall` fails, e.g. because the message is no
+    /// longer in the outbox, or with `TimedOut` if the receiver's MpidManagers didn't reply.  If
+    /// the message was in the outbox, it remains there.
+    RecallFailure(XorName, FailureReason),
+    /// Sent by a Client to its MpidManagers to notify them that it is disconnecting from the
+    /// network.  Until its next `VersionedOnline`, new headers are queued rather than pushed to it.
+    Offline,
+    /// Sent periodically by a connected Client to its MpidManagers so that they continue to deem
+    /// it connected, as per [`PresenceTracker`](struct.PresenceTracker.html).
+    KeepAlive,
+    /// Sent by a Client to its MpidManagers to query the usage of its inbox and outbox.
+    GetAccountInfo,
+    /// Sent by MpidManagers to the Client as a response to a `GetAccountInfo`.
+    GetAccountInfoResponse(AccountInfo),
+    /// Sent by a Client to its MpidManagers to store several new `MpidMessage`s in one request.
+    /// Each message is handled independently, as per `PutMessage`.
+    PutMessages(Vec<MpidMessage>),
+    /// Sent by MpidManagers to the Client as a response to a `PutMessages`.  The contents is the
+    /// name of each message's header, in the order provided, along with `None` if it was stored
+    /// or the reason it wasn't.
+    PutMessagesResponse(Vec<(XorName, Option<FailureReason>)>),
+    /// Sent by a Client to its MpidManagers to delete the named messages from its inbox or outbox,
+    /// as per `DeleteMessage`.
+    DeleteMessages(Vec<XorName>),
+    /// Sent by MpidManagers to the Client as a response to a `DeleteMessages`.  The contents is
+    /// each name provided, along with `None` if it was deleted or the reason it wasn't.
+    DeleteMessagesResponse(Vec<(XorName, Option<FailureReason>)>),
+    /// Sent by a receiving Client to the sender's MpidManagers to delete the named messages'
+    /// headers from the sender's outbox, as per `DeleteHeader`.
+    DeleteHeaders(Vec<XorName>),
+    /// Sent by MpidManagers to the receiver as a response to a `DeleteHeaders`.  The contents is
+    /// each name provided, along with `None` if it was deleted or the reason it wasn't.
+    DeleteHeadersResponse(Vec<(XorName, Option<FailureReason>)>),
+    /// Sent by the receiver's MpidManagers to the sender's MpidManagers as a response to a
+    /// `RecallHeader`.  The contents is the name of the header, along with `None` if it is no
+    /// longer in the receiver's inbox or the reason it couldn't be dropped.
+    RecallHeaderResponse(XorName, Option<FailureReason>),
+}
+
+/// The outcome of a successful `Recall`.  In both cases, the message has been removed from the
+/// sender's outbox and the receiver's MpidManagers have confirmed that its header is no longer in
+/// the receiver's inbox.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, RustcDecodable, RustcEncodable)]
+pub enum RecallOutcome {
+    /// The receiver had not retrieved the message.
+    Recalled,
+    /// The receiver had already retrieved the message via a `GetMessage`, so may retain a copy.
+    AlreadyRetrieved,
+}
+
+impl MpidMessageWrapper {
+    /// Checks the timestamps of the header(s) carried by a `PutMessage`, `PutMultiMessage` or
+    /// `PutHeader` against `now` (in seconds since the UNIX epoch), as per
+    /// [MpidHeader::validate_time()](struct.MpidHeader.html#method.validate_time).
+    ///
+    /// MpidManagers should use this to reject such requests if they have already expired.  All
+    /// other variants are always deemed valid, including `PutMessages`, whose messages should each
+    /// be checked individually so that the others can still be stored.
+    pub fn validate_time(&self, now: u64) -> Result<(), Error> {
+        match *self {
+            MpidMessageWrapper::PutMessage(ref message) => message.header().validate_time(now),
+            MpidMessageWrapper::PutMultiMessage(ref message) => {
+                for header in message.headers() {
+                    try!(header.validate_time(now));
+                }
+                Ok(())
+            }
+            MpidMessageWrapper::PutHeader(ref header) => header.validate_time(now),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the reason carried by a failure response, or `None` if this isn't one.
+    pub fn failure_reason(&self) -> Option<FailureReason> {
+        match *self {
+            MpidMessageWrapper::PutMessageFailure(_, reason) |
+            MpidMessageWrapper::PutHeaderFailure(_, reason) |
+            MpidMessageWrapper::GetMessageFailure(_, reason) |
+            MpidMessageWrapper::DeleteMessageFailure(_, reason) |
+            MpidMessageWrapper::DeleteHeaderFailure(_, reason) |
+            MpidMessageWrapper::RecallFailure(_, reason) |
+            MpidMessageWrapper::Failure(reason) => Some(reason),
+            _ => None,
+        }
+    }
+
+    /// Returns the oldest protocol version able to represent this message, i.e. the version which
+    /// added its variant or, if newer, any `FailureReason` or signed request it carries.
+    ///
+    /// [`MpidEnvelope::encode()`](struct.MpidEnvelope.html#method.encode) refuses to encode the
+    /// message using an older version, since the peer wouldn't be able to decode it.
+    pub fn min_version(&self) -> u32 {
+        match *self {
+            MpidMessageWrapper::Online |
+            MpidMessageWrapper::PutMessage(_) |
+            MpidMessageWrapper::PutHeader(_) |
+            MpidMessageWrapper::GetMessage(_) |
+            MpidMessageWrapper::OutboxHas(_) |
+            MpidMessageWrapper::OutboxHasResponse(_) |
+            MpidMessageWrapper::GetOutboxHeaders |
+            MpidMessageWrapper::GetOutboxHeadersResponse(_) |
+            MpidMessageWrapper::DeleteMessage(_) |
+            MpidMessageWrapper::DeleteHeader(_) |
+            MpidMessageWrapper::PutMultiMessage(_) |
+            MpidMessageWrapper::GetOutboxHeadersPage(_) |
+            MpidMessageWrapper::GetOutboxHeadersPageResponse(_) |
+            MpidMessageWrapper::InboxHas(_) |
+            MpidMessageWrapper::InboxHasResponse(_) |
+            MpidMessageWrapper::GetInboxHeaders(_) |
+            MpidMessageWrapper::GetInboxHeadersResponse(_) |
+            MpidMessageWrapper::PutReceipt(_) |
+            MpidMessageWrapper::VersionedOnline(_, _) |
+            MpidMessageWrapper::OnlineResponse(_) => 1,
+            MpidMessageWrapper::PutMessageFailure(_, reason) |
+            MpidMessageWrapper::PutHeaderFailure(_, reason) |
+            MpidMessageWrapper::GetMessageFailure(_, reason) |
+            MpidMessageWrapper::DeleteMessageFailure(_, reason) |
+            MpidMessageWrapper::DeleteHeaderFailure(_, reason) |
+            MpidMessageWrapper::Failure(reason) => reason.min_version(),
+            MpidMessageWrapper::Signed(ref signed) => cmp::max(2, signed.request().min_version()),
+            MpidMessageWrapper::Recall(_) |
+            MpidMessageWrapper::RecallResponse(_, _) => 3,
+            MpidMessageWrapper::RecallHeader(ref signed) => {
+                cmp::max(3, signed.request().min_version())
+            }
+            MpidMessageWrapper::RecallFailure(_, reason) => cmp::max(3, reason.min_version()),
+            MpidMessageWrapper::Offline |
+            MpidMessageWrapper::KeepAlive => 4,
+            MpidMessageWrapper::GetAccountInfo |
+            MpidMessageWrapper::GetAccountInfoResponse(_) => 5,
+            MpidMessageWrapper::PutMessages(_) |
+            MpidMessageWrapper::DeleteMessages(_) |
+            MpidMessageWrapper::DeleteHeaders(_) => 6,
+            MpidMessageWrapper::PutMessagesResponse(ref results) |
+            MpidMessageWrapper::DeleteMessagesResponse(ref results) |
+            MpidMessageWrapper::DeleteHeadersResponse(ref results) => {
+                results.iter()
+                       .filter_map(|&(_, reason)| reason.map(|reason| reason.min_version()))
+                       .fold(6, cmp::max)
+            }
+            MpidMessageWrapper::RecallHeaderResponse(_, reason) => {
+                cmp::max(8, reason.map_or(1, |reason| reason.min_version()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand;
+    use sodiumoxide::crypto::sign;
+    use xor_name::XorName;
+    use {Error, FailureReason, MpidMessage, PageRequest};
+
+    #[test]
+    fn validate_time() {
+        let (_, secret_key) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let recipient: XorName = rand::random();
+        let message = unwrap_result!(MpidMessage::new(sender,
+                                                      vec![],
+                                                      recipient,
+                                                      vec![],
+                                                      vec![],
+                                                      Some(60),
+                                                      &secret_key));
+        let expires_at = unwrap_option!(message.header().expires_at(), "");
+        let put_message = MpidMessageWrapper::PutMessage(message.clone());
+        let put_header = MpidMessageWrapper::PutHeader(message.header().clone());
+
+        assert!(put_message.validate_time(expires_at).is_ok());
+        assert!(put_header.validate_time(expires_at).is_ok());
+        let too_late = expires_at + ::MAX_CLOCK_SKEW + 1;
+        assert!(put_message.validate_time(too_late).is_err());
+        assert!(put_header.validate_time(too_late).is_err());
+        let get_headers = MpidMessageWrapper::GetOutboxHeadersPage(PageRequest::first(10));
+        assert!(get_headers.validate_time(too_late).is_ok());
+    }
+
+    #[test]
+    fn failure_reason() {
+        let name: XorName = rand::random();
+        let reason = FailureReason::from(&Error::OutboxFull);
+        let failure = MpidMessageWrapper::PutMessageFailure(name, reason);
+        assert_eq!(failure.failure_reason(), Some(FailureReason::OutboxFull));
+        let failure = MpidMessageWrapper::Failure(FailureReason::from(&Error::NotEncrypted));
+        assert_eq!(failure.failure_reason(), Some(FailureReason::InvalidRequest));
+        assert_eq!(MpidMessageWrapper::DeleteMessage(name).failure_reason(), None);
+    }
 }
@@ -0,0 +1,454 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::collections::HashSet;
+use std::fmt::{self, Debug, Formatter};
+
+use sodiumoxide::crypto::box_;
+use sodiumoxide::crypto::sign::{PublicKey, SecretKey, Signature};
+use super::{Attachment, Error, MpidHeader, MpidMessage, MpidMessageWrapper};
+use super::{attachment, encryption, mpid_message};
+use xor_name::XorName;
+
+// The parts of an `MpidMessage` which are specific to a single recipient.
+#[derive(PartialEq, Eq, Hash, Clone, RustcDecodable, RustcEncodable)]
+struct Delivery {
+    header: MpidHeader,
+    sealed_key: Option<Vec<u8>>,
+    signature: Signature,
+}
+
+/// A message with several recipients, all of which share a single copy of the body.
+///
+/// This is equivalent to a separate [`MpidMessage`](struct.MpidMessage.html) per recipient, but
+/// the body is only stored once in the sender's outbox.  Each recipient has its own header, so the
+/// sender's MpidManagers send one `PutHeader` per recipient (see
+/// [`put_headers()`](#method.put_headers)) and can serve each recipient an ordinary `MpidMessage`
+/// via [`message_for()`](#method.message_for).  As each recipient deletes its copy, the
+/// corresponding entry is removed via [`remove_recipient()`](#method.remove_recipient) until
+/// none remain.
+#[derive(PartialEq, Eq, Hash, Clone, RustcDecodable, RustcEncodable)]
+pub struct MpidMultiMessage {
+    body: Vec<u8>,
+    attachments: Vec<Attachment>,
+    deliveries: Vec<Delivery>,
+}
+
+impl MpidMultiMessage {
+    /// Constructor.
+    ///
+    /// The arguments are as per [MpidMessage::new()](struct.MpidMessage.html#method.new), except
+    /// that there is a list of `recipients`.  A header is created and signed for each recipient.
+    /// `body` and `attachments` are shared by all recipients.
+    ///
+    /// An error will be returned if `recipients` is empty or contains duplicates, or for any of the
+    /// reasons `MpidMessage::new()` can fail.
+    pub fn new(sender: XorName,
+               metadata: Vec<u8>,
+               recipients: Vec<XorName>,
+               body: Vec<u8>,
+               attachments: Vec<Attachment>,
+               time_to_live: Option<u64>,
+               secret_key: &SecretKey)
+               -> Result<MpidMultiMessage, Error> {
+        try!(check_recipients(recipients.iter()));
+        let mut deliveries = Vec::with_capacity(recipients.len());
+        let content = try!(mpid_message::content(&body, &attachments, &None));
+        for recipient in recipients {
+            let header = try!(MpidHeader::new(sender.clone(),
+                                              metadata.clone(),
+                                              recipient.clone(),
+                                              &content,
+                                              time_to_live,
+                                              secret_key));
+            deliveries.push(try!(sign_delivery(header,
+                                               recipient,
+                                               &body,
+                                               &attachments,
+                                               None,
+                                               secret_key)));
+        }
+        Ok(MpidMultiMessage {
+            body: body,
+            attachments: attachments,
+            deliveries: deliveries,
+        })
+    }
+
+    /// Constructor for a message whose body can only be read by the recipients.
+    ///
+    /// The arguments are as per
+    /// [MpidMessage::new_encrypted()](struct.MpidMessage.html#method.new_encrypted), except that
+    /// there is a list of `recipients`, each with its public encryption key.  The body is encrypted
+    /// once using a new random symmetric key, and that key is encrypted separately to each
+    /// recipient's public key.
+    pub fn new_encrypted(sender: XorName,
+                         metadata: Vec<u8>,
+                         recipients: Vec<(XorName, box_::PublicKey)>,
+                         body: Vec<u8>,
+                         attachments: Vec<Attachment>,
+                         time_to_live: Option<u64>,
+                         secret_key: &SecretKey)
+                         -> Result<MpidMultiMessage, Error> {
+        Self::create_encrypted(sender,
+                               metadata,
+                               recipients,
+                               body,
+                               attachments,
+                               time_to_live,
+                               false,
+                               secret_key)
+    }
+
+    /// As per [`new_encrypted()`](#method.new_encrypted), except that `metadata` is also encrypted
+    /// to each recipient's public key, as per `MpidMessage::new_encrypted_with_sealed_metadata()`.
+    pub fn new_encrypted_with_sealed_metadata(sender: XorName,
+                                              metadata: Vec<u8>,
+                                              recipients: Vec<(XorName, box_::PublicKey)>,
+                                              body: Vec<u8>,
+                                              attachments: Vec<Attachment>,
+                                              time_to_live: Option<u64>,
+                                              secret_key: &SecretKey)
+                                              -> Result<MpidMultiMessage, Error> {
+        Self::create_encrypted(sender,
+                               metadata,
+                               recipients,
+                               body,
+                               attachments,
+                               time_to_live,
+                               true,
+                               secret_key)
+    }
+
+    #[cfg_attr(feature="clippy", allow(too_many_arguments))]
+    fn create_encrypted(sender: XorName,
+                        metadata: Vec<u8>,
+                        recipients: Vec<(XorName, box_::PublicKey)>,
+                        body: Vec<u8>,
+                        attachments: Vec<Attachment>,
+                        time_to_live: Option<u64>,
+                        seal_metadata: bool,
+                        secret_key: &SecretKey)
+                        -> Result<MpidMultiMessage, Error> {
+        try!(check_recipients(recipients.iter().map(|&(ref recipient, _)| recipient)));
+        let key = encryption::generate_key();
+        let encrypted_body = encryption::encrypt(&body, &key);
+        let attachments = attachment::encrypt_data_maps(attachments, &key);
+        let mut deliveries = Vec::with_capacity(recipients.len());
+        for (recipient, recipient_public_key) in recipients {
+            let sealed_key = Some(encryption::seal(&key.0, &recipient_public_key));
+            let content = try!(mpid_message::content(&encrypted_body, &attachments, &sealed_key));
+            let header = if seal_metadata {
+                try!(MpidHeader::new_with_sealed_metadata(sender.clone(),
+                                                          metadata.clone(),
+                                                          recipient.clone(),
+                                                          &content,
+                                                          time_to_live,
+                                                          &recipient_public_key,
+                                                          secret_key))
+            } else {
+                try!(MpidHeader::new(sender.clone(),
+                                     metadata.clone(),
+                                     recipient.clone(),
+                                     &content,
+                                     time_to_live,
+                                     secret_key))
+            };
+            deliveries.push(try!(sign_delivery(header,
+                                               recipient,
+                                               &encrypted_body,
+                                               &attachments,
+                                               sealed_key,
+                                               secret_key)));
+        }
+        Ok(MpidMultiMessage {
+            body: encrypted_body,
+            attachments: attachments,
+            deliveries: deliveries,
+        })
+    }
+
+    /// The body shared by all recipients.  If the message was constructed via
+    /// [`new_encrypted()`](#method.new_encrypted), this is the encrypted form.
+    pub fn body(&self) -> &Vec<u8> {
+        &self.body
+    }
+
+    /// The attachments shared by all recipients.  If the message was constructed via
+    /// [`new_encrypted()`](#method.new_encrypted), the data maps are in encrypted form.
+    pub fn attachments(&self) -> &Vec<Attachment> {
+        &self.attachments
+    }
+
+    /// Whether the body is encrypted, i.e. whether the message was constructed via
+    /// [`new_encrypted()`](#method.new_encrypted).
+    pub fn is_encrypted(&self) -> bool {
+        self.deliveries.first().map_or(false, |delivery| delivery.sealed_key.is_some())
+    }
+
+    /// The names of the recipients which haven't yet been removed.
+    pub fn recipients(&self) -> Vec<&XorName> {
+        self.deliveries.iter().map(|delivery| delivery.header.recipient()).collect()
+    }
+
+    /// The headers of the recipients which haven't yet been removed.
+    pub fn headers(&self) -> Vec<&MpidHeader> {
+        self.deliveries.iter().map(|delivery| &delivery.header).collect()
+    }
+
+    /// A `PutHeader` for each recipient which hasn't yet been removed, to be sent by the sender's
+    /// MpidManagers to the respective recipient's MpidManagers.
+    pub fn put_headers(&self) -> Vec<MpidMessageWrapper> {
+        self.deliveries
+            .iter()
+            .map(|delivery| MpidMessageWrapper::PutHeader(delivery.header.clone()))
+            .collect()
+    }
+
+    /// Returns the individual message for the recipient whose header has the given name, or
+    /// `None` if there is no such recipient.
+    ///
+    /// The returned message is identical to one constructed via
+    /// [MpidMessage::new()](struct.MpidMessage.html#method.new) (or `new_encrypted()`) for that
+    /// recipient alone, and so can be verified and decrypted in the same way.
+    pub fn message_for(&self, header_name: &XorName) -> Option<MpidMessage> {
+        self.position(header_name).map(|index| {
+            let delivery = self.deliveries[index].clone();
+            mpid_message::from_parts(delivery.header,
+                                     self.body.clone(),
+                                     self.attachments.clone(),
+                                     delivery.sealed_key,
+                                     delivery.signature)
+        })
+    }
+
+    /// Removes the recipient whose header has the given name, e.g. once it has deleted the message.
+    /// Returns `false` if there is no such recipient.
+    pub fn remove_recipient(&mut self, header_name: &XorName) -> bool {
+        match self.position(header_name) {
+            Some(index) => {
+                let _ = self.deliveries.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether all recipients have been removed, in which case the message can be deleted.
+    pub fn is_empty(&self) -> bool {
+        self.deliveries.is_empty()
+    }
+
+    /// Validates the message for each remaining recipient against the provided `PublicKey`, as per
+    /// [MpidMessage::verify()](struct.MpidMessage.html#method.verify).
+    pub fn verify(&self, public_key: &PublicKey) -> bool {
+        self.deliveries.iter().all(|delivery| {
+            let message = mpid_message::from_parts(delivery.header.clone(),
+                                                   self.body.clone(),
+                                                   self.attachments.clone(),
+                                                   delivery.sealed_key.clone(),
+                                                   delivery.signature);
+            message.verify(public_key)
+        })
+    }
+
+    fn position(&self, header_name: &XorName) -> Option<usize> {
+        self.deliveries.iter().position(|delivery| {
+            match delivery.header.name() {
+                Ok(name) => name == *header_name,
+                Err(_) => false,
+            }
+        })
+    }
+}
+
+impl Debug for MpidMultiMessage {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(formatter,
+               "MpidMultiMessage {{ headers: {:?}, body: {}, attachments: {:?}, encrypted: {} }}",
+               self.headers(),
+               ::format_binary_array(&self.body),
+               self.attachments,
+               self.is_encrypted())
+    }
+}
+
+fn check_recipients<'a, I: Iterator<Item = &'a XorName>>(recipients: I) -> Result<(), Error> {
+    let mut seen = HashSet::new();
+    for recipient in recipients {
+        if !seen.insert(recipient) {
+            return Err(Error::InvalidRecipients);
+        }
+    }
+    if seen.is_empty() {
+        return Err(Error::InvalidRecipients);
+    }
+    Ok(())
+}
+
+fn sign_delivery(header: MpidHeader,
+                 recipient: XorName,
+                 body: &[u8],
+                 attachments: &[Attachment],
+                 sealed_key: Option<Vec<u8>>,
+                 secret_key: &SecretKey)
+                 -> Result<Delivery, Error> {
+    let message = try!(mpid_message::create(header,
+                                            recipient,
+                                            body.to_vec(),
+                                            attachments.to_vec(),
+                                            sealed_key,
+                                            secret_key));
+    let (header, sealed_key, signature) = mpid_message::into_parts(message);
+    Ok(Delivery {
+        header: header,
+        sealed_key: sealed_key,
+        signature: signature,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand;
+    use sodiumoxide::crypto::{box_, sign};
+    use xor_name::XorName;
+    use MpidMessageWrapper;
+
+    #[test]
+    fn full() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let recipients: Vec<XorName> = (0..5).map(|_| rand::random()).collect();
+        let body = ::generate_random_bytes(1000);
+
+        // Check with no recipients and with duplicate recipients.
+        assert!(MpidMultiMessage::new(sender.clone(),
+                                      vec![],
+                                      vec![],
+                                      body.clone(),
+                                      vec![],
+                                      None,
+                                      &secret_key)
+                    .is_err());
+        assert!(MpidMultiMessage::new(sender.clone(),
+                                      vec![],
+                                      vec![recipients[0].clone(), recipients[0].clone()],
+                                      body.clone(),
+                                      vec![],
+                                      None,
+                                      &secret_key)
+                    .is_err());
+
+        let mut message = unwrap_result!(MpidMultiMessage::new(sender,
+                                                               vec![],
+                                                               recipients.clone(),
+                                                               body.clone(),
+                                                               vec![],
+                                                               None,
+                                                               &secret_key));
+        assert!(!message.is_encrypted());
+        assert!(message.verify(&public_key));
+        assert_eq!(message.headers().len(), recipients.len());
+        let put_headers = message.put_headers();
+        assert_eq!(put_headers.len(), recipients.len());
+
+        // Each recipient gets a valid individual message sharing the body.
+        let names: Vec<XorName> = message.headers()
+                                         .iter()
+                                         .map(|header| unwrap_result!(header.name()))
+                                         .collect();
+        for (index, name) in names.iter().enumerate() {
+            let individual = unwrap_option!(message.message_for(name), "");
+            assert!(individual.verify(&public_key));
+            assert_eq!(*individual.recipient(), recipients[index]);
+            assert_eq!(*individual.body(), body);
+            assert_eq!(unwrap_result!(individual.name()), *name);
+            match put_headers[index] {
+                MpidMessageWrapper::PutHeader(ref header) => assert!(header == individual.header()),
+                _ => panic!("Expected PutHeader"),
+            }
+        }
+        assert!(message.message_for(&rand::random()).is_none());
+
+        // Remove recipients one at a time.
+        for name in &names {
+            assert!(!message.is_empty());
+            assert!(message.remove_recipient(name));
+            assert!(!message.remove_recipient(name));
+            assert!(message.message_for(name).is_none());
+            assert!(message.verify(&public_key));
+        }
+        assert!(message.is_empty());
+    }
+
+    #[test]
+    fn encrypted() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let keys: Vec<(box_::PublicKey, box_::SecretKey)> = (0..3)
+                                                                .map(|_| box_::gen_keypair())
+                                                                .collect();
+        let recipients: Vec<(XorName, box_::PublicKey)> = keys.iter()
+                                                              .map(|&(ref public_key, _)| {
+                                                                  (rand::random(),
+                                                                   public_key.clone())
+                                                              })
+                                                              .collect();
+        let body = ::generate_random_bytes(1000);
+
+        let metadata = ::generate_random_bytes(10);
+
+        let message = unwrap_result!(MpidMultiMessage::new_encrypted(sender.clone(),
+                                                                     metadata.clone(),
+                                                                     recipients.clone(),
+                                                                     body.clone(),
+                                                                     vec![],
+                                                                     None,
+                                                                     &secret_key));
+        assert!(message.is_encrypted());
+        assert!(message.headers().iter().all(|header| !header.is_metadata_sealed()));
+        assert!(message.verify(&public_key));
+        assert!(*message.body() != body);
+
+        // Each recipient can only decrypt its own message.
+        for (index, header) in message.headers().iter().enumerate() {
+            let name = unwrap_result!(header.name());
+            let individual = unwrap_option!(message.message_for(&name), "");
+            assert!(individual.verify(&public_key));
+            assert_eq!(unwrap_result!(individual.decrypt_body(&keys[index].1)), body);
+            let other_index = (index + 1) % keys.len();
+            assert!(individual.decrypt_body(&keys[other_index].1).is_err());
+        }
+
+        // With sealed metadata, each recipient can only open its own header's metadata.
+        let message =
+            unwrap_result!(MpidMultiMessage::new_encrypted_with_sealed_metadata(sender,
+                                                                                metadata.clone(),
+                                                                                recipients,
+                                                                                body,
+                                                                                vec![],
+                                                                                None,
+                                                                                &secret_key));
+        assert!(message.verify(&public_key));
+        for (index, header) in message.headers().iter().enumerate() {
+            assert_eq!(unwrap_result!(header.open_metadata(&keys[index].1)), metadata);
+            let other_index = (index + 1) % keys.len();
+            assert!(header.open_metadata(&keys[other_index].1).is_err());
+        }
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use maidsafe_utilities::serialisation::serialise;
+use sodiumoxide::crypto::sign::{self, PublicKey, SecretKey, Signature};
+use super::{Error, MpidHeader};
+use xor_name::XorName;
+
+/// What a [receipt](struct.MpidReceipt.html) acknowledges.
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, RustcDecodable, RustcEncodable)]
+pub enum ReceiptKind {
+    /// The recipient has retrieved the message.
+    Delivered,
+    /// The recipient has read the message.
+    Read,
+}
+
+#[derive(PartialEq, Eq, Hash, Clone, Debug, RustcDecodable, RustcEncodable)]
+struct Detail {
+    kind: ReceiptKind,
+    header_name: XorName,
+    recipient: XorName,
+    created_at: u64,
+}
+
+/// An acknowledgement of a message, signed by its recipient and sent back to the sender's
+/// MpidManagers.  The sender can use this to prove that the recipient acknowledged the message.
+#[derive(PartialEq, Eq, Hash, Clone, Debug, RustcDecodable, RustcEncodable)]
+pub struct MpidReceipt {
+    detail: Detail,
+    signature: Signature,
+}
+
+impl MpidReceipt {
+    /// Constructor.
+    ///
+    /// `header` is the header of the message being acknowledged, and `secret_key` is the
+    /// recipient's signing key.  The receipt is signed over its kind, the header's name, the
+    /// header's recipient and the current time.
+    pub fn new(kind: ReceiptKind,
+               header: &MpidHeader,
+               secret_key: &SecretKey)
+               -> Result<MpidReceipt, Error> {
+        let detail = Detail {
+            kind: kind,
+            header_name: try!(header.name()),
+            recipient: header.recipient().clone(),
+            created_at: ::seconds_since_epoch(),
+        };
+        let encoded = try!(serialise(&detail));
+        Ok(MpidReceipt {
+            detail: detail,
+            signature: sign::sign_detached(&encoded, secret_key),
+        })
+    }
+
+    /// Getter for what the receipt acknowledges.
+    pub fn kind(&self) -> ReceiptKind {
+        self.detail.kind
+    }
+
+    /// Getter for the name of the acknowledged message's header.
+    pub fn header_name(&self) -> &XorName {
+        &self.detail.header_name
+    }
+
+    /// Getter for the name of the recipient who signed the receipt.
+    pub fn recipient(&self) -> &XorName {
+        &self.detail.recipient
+    }
+
+    /// Getter for the creation time of the receipt, in seconds since the UNIX epoch.
+    pub fn created_at(&self) -> u64 {
+        self.detail.created_at
+    }
+
+    /// Getter for the signature of the receipt.
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    /// Validates the receipt's signature against `recipient_public_key`, and checks that the
+    /// recipient's name is the one derived from it via
+    /// [`name_from_public_key()`](fn.name_from_public_key.html).
+    pub fn verify(&self, recipient_public_key: &PublicKey) -> bool {
+        if self.detail.recipient != ::name_from_public_key(recipient_public_key) {
+            return false;
+        }
+        match serialise(&self.detail) {
+            Ok(encoded) => sign::verify_detached(&self.signature, &encoded, recipient_public_key),
+            Err(_) => false,
+        }
+    }
+
+    /// As per [`verify()`](#method.verify), and also checks that the receipt acknowledges `header`,
+    /// i.e. that it names `header` and was signed by `header`'s recipient.
+    pub fn verify_for(&self, header: &MpidHeader, recipient_public_key: &PublicKey) -> bool {
+        match header.name() {
+            Ok(name) => {
+                name == self.detail.header_name && *header.recipient() == self.detail.recipient &&
+                self.verify(recipient_public_key)
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand;
+    use sodiumoxide::crypto::sign;
+    use xor_name::XorName;
+    use MpidHeader;
+
+    #[test]
+    fn full() {
+        let (_, sender_secret_key) = sign::gen_keypair();
+        let (recipient_public_key, recipient_secret_key) = sign::gen_keypair();
+        let (other_public_key, other_secret_key) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let recipient = ::name_from_public_key(&recipient_public_key);
+        let header = unwrap_result!(MpidHeader::new(sender.clone(),
+                                                    vec![],
+                                                    recipient.clone(),
+                                                    &[],
+                                                    None,
+                                                    &sender_secret_key));
+        let other_header = unwrap_result!(MpidHeader::new(sender,
+                                                          vec![],
+                                                          recipient.clone(),
+                                                          &[],
+                                                          None,
+                                                          &sender_secret_key));
+
+        let receipt = unwrap_result!(MpidReceipt::new(ReceiptKind::Read,
+                                                      &header,
+                                                      &recipient_secret_key));
+        assert_eq!(receipt.kind(), ReceiptKind::Read);
+        assert_eq!(*receipt.header_name(), unwrap_result!(header.name()));
+        assert_eq!(*receipt.recipient(), recipient);
+        assert!(receipt.verify(&recipient_public_key));
+        assert!(receipt.verify_for(&header, &recipient_public_key));
+        assert!(!receipt.verify(&other_public_key));
+        assert!(!receipt.verify_for(&other_header, &recipient_public_key));
+
+        // A receipt signed by anyone other than the recipient is rejected.
+        let forged = unwrap_result!(MpidReceipt::new(ReceiptKind::Delivered,
+                                                     &header,
+                                                     &other_secret_key));
+        assert!(!forged.verify(&other_public_key));
+        assert!(!forged.verify_for(&header, &recipient_public_key));
+
+        // Changing the kind invalidates the signature.
+        let mut tampered = receipt.clone();
+        tampered.detail.kind = ReceiptKind::Delivered;
+        assert!(!tampered.verify(&recipient_public_key));
+    }
+}
@@ -0,0 +1,381 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::collections::BTreeMap;
+
+use maidsafe_utilities::serialisation::serialise;
+use super::{BoxUsage, Error, HeaderPage, MpidHeader, MpidMessage, MpidMultiMessage, PageRequest,
+            MAX_OUTBOX_SIZE};
+use super::header_page;
+use xor_name::XorName;
+
+enum Content {
+    Single(MpidMessage),
+    // One recipient of the multi-message held under the given key in `multi_messages`.
+    Multi(XorName, MpidHeader),
+}
+
+struct Entry {
+    content: Content,
+    size: u64,
+    retrieved: bool,
+}
+
+impl Entry {
+    fn header(&self) -> &MpidHeader {
+        match self.content {
+            Content::Single(ref message) => message.header(),
+            Content::Multi(_, ref header) => header,
+        }
+    }
+}
+
+struct MultiEntry {
+    message: MpidMultiMessage,
+    size: u64,
+}
+
+/// Used by MpidManagers to hold the messages sent by a single Client, keyed by header name, until
+/// they are deleted by the recipient or recalled by the sender.
+///
+/// Each message is charged its serialised size against the outbox's limit.  A multi-recipient
+/// message is held once, with an entry per recipient, and charged once; as recipients are removed
+/// its charge shrinks to the size of what remains, and it is freed along with the last recipient.
+pub struct Outbox {
+    messages: BTreeMap<XorName, Entry>,
+    multi_messages: BTreeMap<XorName, MultiEntry>,
+    bytes_used: u64,
+    limit: u64,
+}
+
+impl Outbox {
+    /// Constructs an empty outbox limited to [`MAX_OUTBOX_SIZE`](constant.MAX_OUTBOX_SIZE.html).
+    pub fn new() -> Outbox {
+        Outbox::with_limit(MAX_OUTBOX_SIZE as u64)
+    }
+
+    /// Constructs an empty outbox limited to `limit` bytes.
+    pub fn with_limit(limit: u64) -> Outbox {
+        Outbox {
+            messages: BTreeMap::new(),
+            multi_messages: BTreeMap::new(),
+            bytes_used: 0,
+            limit: limit,
+        }
+    }
+
+    /// Stores `message`, returning the name of its header.  Storing a message which is already
+    /// held has no effect.
+    ///
+    /// `Error::OutboxFull` is returned if the message would take the outbox over its limit.
+    pub fn put(&mut self, message: MpidMessage) -> Result<XorName, Error> {
+        let name = try!(message.name());
+        if self.messages.contains_key(&name) {
+            return Ok(name);
+        }
+        let size = try!(message_size(&message));
+        if !self.usage().can_accept(size) {
+            return Err(Error::OutboxFull);
+        }
+        self.bytes_used += size;
+        let _ = self.messages.insert(name.clone(),
+                                     Entry {
+                                         content: Content::Single(message),
+                                         size: size,
+                                         retrieved: false,
+                                     });
+        Ok(name)
+    }
+
+    /// Stores a multi-recipient message, returning the names of the recipients' headers.  Each
+    /// recipient's message can then be accessed by its header name as though it had been stored
+    /// individually via [`put()`](#method.put).  Storing a message which is already held has no
+    /// effect.
+    ///
+    /// `Error::InvalidRecipients` is returned if the message has no remaining recipients,
+    /// `Error::DuplicateGuid` if one of its header names is already held for another message, and
+    /// `Error::OutboxFull` if it would take the outbox over its limit.
+    pub fn put_multi(&mut self, message: MpidMultiMessage) -> Result<Vec<XorName>, Error> {
+        let mut headers = Vec::new();
+        for header in message.headers() {
+            headers.push((try!(header.name()), header.clone()));
+        }
+        let key = match headers.first() {
+            Some(&(ref name, _)) => name.clone(),
+            None => return Err(Error::InvalidRecipients),
+        };
+        let names = headers.iter().map(|&(ref name, _)| name.clone()).collect::<Vec<_>>();
+        if self.multi_messages.contains_key(&key) {
+            return Ok(names);
+        }
+        if names.iter().any(|name| self.messages.contains_key(name)) {
+            return Err(Error::DuplicateGuid);
+        }
+        let size = try!(multi_message_size(&message));
+        if !self.usage().can_accept(size) {
+            return Err(Error::OutboxFull);
+        }
+        self.bytes_used += size;
+        let _ = self.multi_messages.insert(key.clone(),
+                                           MultiEntry {
+                                               message: message,
+                                               size: size,
+                                           });
+        for (name, header) in headers {
+            let _ = self.messages.insert(name,
+                                         Entry {
+                                             content: Content::Multi(key.clone(), header),
+                                             size: 0,
+                                             retrieved: false,
+                                         });
+        }
+        Ok(names)
+    }
+
+    /// Whether a message with the given header name is held.
+    pub fn contains(&self, name: &XorName) -> bool {
+        self.messages.contains_key(name)
+    }
+
+    /// Returns the header of the message with the given header name.
+    pub fn header(&self, name: &XorName) -> Option<&MpidHeader> {
+        self.messages.get(name).map(Entry::header)
+    }
+
+    /// Returns the message with the given header name.
+    pub fn get(&self, name: &XorName) -> Option<MpidMessage> {
+        self.messages.get(name).and_then(|entry| self.message(name, entry))
+    }
+
+    /// Returns the message with the given header name, recording that it has been retrieved by
+    /// the recipient via a `GetMessage`.
+    pub fn retrieve(&mut self, name: &XorName) -> Option<MpidMessage> {
+        if let Some(entry) = self.messages.get_mut(name) {
+            entry.retrieved = true;
+        }
+        self.get(name)
+    }
+
+    /// Whether the message with the given header name has been [retrieved](#method.retrieve).
+    pub fn is_retrieved(&self, name: &XorName) -> bool {
+        self.messages.get(name).map_or(false, |entry| entry.retrieved)
+    }
+
+    /// Removes and returns the message with the given header name, freeing its space.  For a
+    /// recipient of a multi-recipient message, only the space no longer needed by the remaining
+    /// recipients is freed.
+    pub fn remove(&mut self, name: &XorName) -> Option<MpidMessage> {
+        let entry = match self.messages.remove(name) {
+            Some(entry) => entry,
+            None => return None,
+        };
+        let message = self.message(name, &entry);
+        self.bytes_used -= entry.size;
+        if let Content::Multi(key, _) = entry.content {
+            let emptied = match self.multi_messages.get_mut(&key) {
+                Some(multi_entry) => {
+                    let _ = multi_entry.message.remove_recipient(name);
+                    let size = if multi_entry.message.is_empty() {
+                        0
+                    } else {
+                        multi_message_size(&multi_entry.message).unwrap_or(multi_entry.size)
+                    };
+                    self.bytes_used -= multi_entry.size - size;
+                    multi_entry.size = size;
+                    multi_entry.message.is_empty()
+                }
+                None => false,
+            };
+            if emptied {
+                let _ = self.multi_messages.remove(&key);
+            }
+        }
+        message
+    }
+
+    /// Answers an `OutboxHas`, returning the headers of those named messages which are held.
+    pub fn has(&self, names: &[XorName]) -> Vec<MpidHeader> {
+        names.iter()
+             .filter_map(|name| self.messages.get(name))
+             .map(|entry| entry.header().clone())
+             .collect()
+    }
+
+    /// Answers a `GetOutboxHeaders`, returning the headers of all messages held.
+    pub fn all_headers(&self) -> Vec<MpidHeader> {
+        self.messages.values().map(|entry| entry.header().clone()).collect()
+    }
+
+    /// Answers a `GetOutboxHeadersPage`, as per
+    /// [`HeaderPage::new()`](struct.HeaderPage.html#method.new).
+    pub fn headers(&self, request: &PageRequest) -> Result<HeaderPage, Error> {
+        header_page::select(self.messages.iter().map(|(name, entry)| (name, entry.header())),
+                            request)
+    }
+
+    /// The usage of the outbox, e.g. for answering a `GetAccountInfo`.  Each recipient of a
+    /// multi-recipient message counts as a message.
+    pub fn usage(&self) -> BoxUsage {
+        BoxUsage::new(self.bytes_used, self.messages.len() as u64, self.limit)
+    }
+
+    /// The number of messages held.
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// Whether the outbox is empty.
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    fn message(&self, name: &XorName, entry: &Entry) -> Option<MpidMessage> {
+        match entry.content {
+            Content::Single(ref message) => Some(message.clone()),
+            Content::Multi(ref key, _) => {
+                self.multi_messages
+                    .get(key)
+                    .and_then(|multi_entry| multi_entry.message.message_for(name))
+            }
+        }
+    }
+}
+
+impl Default for Outbox {
+    fn default() -> Outbox {
+        Outbox::new()
+    }
+}
+
+/// The size charged against the outbox's limit for `message`, i.e. its serialised size.
+pub fn message_size(message: &MpidMessage) -> Result<u64, Error> {
+    Ok(try!(serialise(message)).len() as u64)
+}
+
+/// As per `message_size()`, but for a multi-recipient message.
+pub fn multi_message_size(message: &MpidMultiMessage) -> Result<u64, Error> {
+    Ok(try!(serialise(message)).len() as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand;
+    use sodiumoxide::crypto::sign;
+    use xor_name::XorName;
+    use {Error, MpidMessage, MpidMultiMessage, PageRequest};
+
+    #[test]
+    fn full() {
+        let (_, secret_key) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let new_message = || {
+            let recipient: XorName = rand::random();
+            unwrap_result!(MpidMessage::new(sender.clone(),
+                                            vec![],
+                                            recipient,
+                                            ::generate_random_bytes(100),
+                                            vec![],
+                                            None,
+                                            &secret_key))
+        };
+        let message1 = new_message();
+        let message2 = new_message();
+        let size = unwrap_result!(message_size(&message1));
+        assert_eq!(size, unwrap_result!(message_size(&message2)));
+
+        // Fill an outbox with room for exactly two messages.
+        let mut outbox = Outbox::with_limit(2 * size + 1);
+        let name1 = unwrap_result!(outbox.put(message1.clone()));
+        assert_eq!(unwrap_result!(outbox.put(message1.clone())), name1);
+        let name2 = unwrap_result!(outbox.put(message2.clone()));
+        assert_eq!(outbox.len(), 2);
+        assert_eq!(outbox.usage().bytes_used(), 2 * size);
+        match outbox.put(new_message()) {
+            Err(Error::OutboxFull) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+
+        // Queries.
+        let unknown: XorName = rand::random();
+        assert_eq!(outbox.has(&[name2.clone(), unknown]), vec![message2.header().clone()]);
+        let page = unwrap_result!(outbox.headers(&PageRequest::first(10)));
+        assert_eq!(page.headers().len(), 2);
+        assert!(page.is_last());
+        assert_eq!(outbox.all_headers().len(), 2);
+        assert!(!outbox.is_retrieved(&name1));
+        assert_eq!(outbox.retrieve(&name1), Some(message1.clone()));
+        assert!(outbox.is_retrieved(&name1));
+
+        // Removal frees space.
+        assert_eq!(outbox.remove(&name1), Some(message1));
+        assert!(outbox.remove(&name1).is_none());
+        assert_eq!(outbox.usage().bytes_used(), size);
+        assert!(outbox.put(new_message()).is_ok());
+    }
+
+    #[test]
+    fn multi() {
+        let (_, secret_key) = sign::gen_keypair();
+        let sender: XorName = rand::random();
+        let recipients: Vec<XorName> = (0..3).map(|_| rand::random()).collect();
+        let message = unwrap_result!(MpidMultiMessage::new(sender,
+                                                           vec![],
+                                                           recipients,
+                                                           ::generate_random_bytes(1000),
+                                                           vec![],
+                                                           None,
+                                                           &secret_key));
+        let size = unwrap_result!(multi_message_size(&message));
+
+        // The body is charged once, however many recipients there are.
+        let mut outbox = Outbox::with_limit(size + 1);
+        let names = unwrap_result!(outbox.put_multi(message.clone()));
+        assert_eq!(unwrap_result!(outbox.put_multi(message.clone())), names);
+        assert_eq!(outbox.len(), 3);
+        assert_eq!(outbox.usage().bytes_used(), size);
+        for name in &names {
+            let individual = unwrap_option!(message.message_for(name), "");
+            assert_eq!(outbox.header(name), Some(individual.header()));
+            assert_eq!(outbox.get(name), Some(individual.clone()));
+            assert!(!outbox.is_retrieved(name));
+            assert_eq!(outbox.retrieve(name), Some(individual));
+            assert!(outbox.is_retrieved(name));
+        }
+
+        // Removing recipients frees their deliveries, and the last frees the body.
+        let mut remaining = message.clone();
+        assert!(outbox.remove(&names[0]).is_some());
+        assert!(remaining.remove_recipient(&names[0]));
+        assert_eq!(outbox.usage().bytes_used(),
+                   unwrap_result!(multi_message_size(&remaining)));
+        assert!(outbox.get(&names[0]).is_none());
+        assert!(outbox.remove(&names[1]).is_some());
+        assert!(outbox.remove(&names[2]).is_some());
+        assert!(outbox.is_empty());
+        assert_eq!(outbox.usage().bytes_used(), 0);
+
+        // A message with no recipients left is rejected.
+        for name in &names[1..] {
+            assert!(remaining.remove_recipient(name));
+        }
+        match outbox.put_multi(remaining) {
+            Err(Error::InvalidRecipients) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+}
@@ -0,0 +1,164 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::collections::HashMap;
+
+use xor_name::XorName;
+
+/// Used by MpidManagers to track which Clients are connected, and so to decide whether a new
+/// header is pushed to its recipient immediately or queued until the recipient's next
+/// `VersionedOnline`.
+///
+/// A Client is deemed connected from its `VersionedOnline` until its `Offline`, or until `timeout`
+/// seconds pass without a `VersionedOnline` or `KeepAlive` from it.  All times are in seconds since
+/// the UNIX epoch.
+///
+/// Only the names of queued headers are held, since the headers themselves are already stored in
+/// the recipient's [`Inbox`](struct.Inbox.html).  MpidManagers should only queue headers which
+/// they have stored, and remove them from the queue via
+/// [`remove_queued()`](#method.remove_queued) when removing them from the inbox, so that the queue
+/// never outgrows the inbox.
+pub struct PresenceTracker {
+    timeout: u64,
+    last_seen: HashMap<XorName, u64>,
+    queued: HashMap<XorName, Vec<XorName>>,
+}
+
+impl PresenceTracker {
+    /// Constructor.
+    pub fn new(timeout: u64) -> PresenceTracker {
+        PresenceTracker {
+            timeout: timeout,
+            last_seen: HashMap::new(),
+            queued: HashMap::new(),
+        }
+    }
+
+    /// Records a `VersionedOnline` from `client`, returning the names of any headers queued for it
+    /// while it was offline, in the order they arrived.
+    pub fn online(&mut self, client: XorName, now: u64) -> Vec<XorName> {
+        let queued = self.queued.remove(&client).unwrap_or_else(Vec::new);
+        let _ = self.last_seen.insert(client, now);
+        queued
+    }
+
+    /// Records a `KeepAlive` from `client`.  Returns `false` if `client` isn't connected, in which
+    /// case it should send `VersionedOnline` instead.
+    pub fn keep_alive(&mut self, client: &XorName, now: u64) -> bool {
+        if !self.is_online(client, now) {
+            return false;
+        }
+        let _ = self.last_seen.insert(client.clone(), now);
+        true
+    }
+
+    /// Records an `Offline` from `client`.
+    pub fn offline(&mut self, client: &XorName) {
+        let _ = self.last_seen.remove(client);
+    }
+
+    /// Whether `client` is currently connected.
+    pub fn is_online(&self, client: &XorName, now: u64) -> bool {
+        self.last_seen
+            .get(client)
+            .map_or(false, |last_seen| last_seen.saturating_add(self.timeout) > now)
+    }
+
+    /// Decides what to do with a new header for `recipient`.  If the recipient is connected,
+    /// `true` is returned and the header should be pushed immediately; otherwise its name is queued
+    /// until the recipient's next [`online()`](#method.online) and `false` is returned.
+    pub fn deliver(&mut self, recipient: &XorName, header_name: XorName, now: u64) -> bool {
+        if self.is_online(recipient, now) {
+            return true;
+        }
+        self.queued.entry(recipient.clone()).or_insert_with(Vec::new).push(header_name);
+        false
+    }
+
+    /// Removes the queued header with the given name, e.g. where the message has been deleted or
+    /// recalled.  Returns `false` if there was no such header.
+    pub fn remove_queued(&mut self, client: &XorName, header_name: &XorName) -> bool {
+        let (removed, now_empty) = match self.queued.get_mut(client) {
+            Some(headers) => {
+                let count = headers.len();
+                headers.retain(|name| name != header_name);
+                (headers.len() != count, headers.is_empty())
+            }
+            None => return false,
+        };
+        if now_empty {
+            let _ = self.queued.remove(client);
+        }
+        removed
+    }
+
+    /// Marks as offline, and returns, every Client whose connection has timed out by `now`.
+    pub fn expire(&mut self, now: u64) -> Vec<XorName> {
+        let timeout = self.timeout;
+        let expired = self.last_seen
+                          .iter()
+                          .filter(|&(_, last_seen)| last_seen.saturating_add(timeout) <= now)
+                          .map(|(client, _)| client.clone())
+                          .collect::<Vec<_>>();
+        for client in &expired {
+            let _ = self.last_seen.remove(client);
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand;
+    use xor_name::XorName;
+
+    #[test]
+    fn full() {
+        let client: XorName = rand::random();
+        let mut tracker = PresenceTracker::new(30);
+
+        // Headers for an offline client are queued until it comes online.
+        let name1: XorName = rand::random();
+        let name2: XorName = rand::random();
+        let name3: XorName = rand::random();
+        assert!(!tracker.deliver(&client, name1.clone(), 100));
+        assert!(!tracker.deliver(&client, name2.clone(), 100));
+        assert!(!tracker.deliver(&client, name3.clone(), 100));
+        assert!(tracker.remove_queued(&client, &name2));
+        assert!(!tracker.remove_queued(&client, &name2));
+        assert!(!tracker.keep_alive(&client, 100));
+        assert_eq!(tracker.online(client.clone(), 110), vec![name1, name3]);
+
+        // Headers for an online client are pushed immediately.
+        assert!(tracker.deliver(&client, rand::random(), 120));
+        assert!(tracker.online(client.clone(), 120).is_empty());
+
+        // Keep-alives extend the connection until it times out.
+        assert!(tracker.keep_alive(&client, 140));
+        assert!(tracker.expire(160).is_empty());
+        assert!(tracker.is_online(&client, 169));
+        assert_eq!(tracker.expire(170), vec![client.clone()]);
+        assert!(!tracker.is_online(&client, 170));
+        assert!(!tracker.deliver(&client, rand::random(), 170));
+
+        // An explicit offline takes effect immediately.
+        assert_eq!(tracker.online(client.clone(), 180).len(), 1);
+        tracker.offline(&client);
+        assert!(!tracker.is_online(&client, 180));
+    }
+}
@@ -0,0 +1,146 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::collections::HashMap;
+
+use rand;
+use super::{MpidEnvelope, MpidMessageWrapper};
+
+struct PendingRequest {
+    request: MpidMessageWrapper,
+    expires_at: u64,
+}
+
+/// Used by a Client to assign request IDs to outgoing messages and to pair incoming responses with
+/// the requests which caused them.
+///
+/// All times are in seconds since the UNIX epoch.
+pub struct RequestTracker {
+    timeout: u64,
+    pending: HashMap<u64, PendingRequest>,
+}
+
+impl RequestTracker {
+    /// Constructor.  Requests which haven't received a response within `timeout` seconds are
+    /// reported by [`expire()`](#method.expire).
+    pub fn new(timeout: u64) -> RequestTracker {
+        RequestTracker {
+            timeout: timeout,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Assigns a new random request ID to `request`, records it as pending and returns the
+    /// envelope to be sent.
+    pub fn send(&mut self, request: MpidMessageWrapper, now: u64) -> MpidEnvelope {
+        let mut request_id: u64 = rand::random();
+        while self.pending.contains_key(&request_id) {
+            request_id = rand::random();
+        }
+        let _ = self.pending.insert(request_id,
+                                    PendingRequest {
+                                        request: request.clone(),
+                                        expires_at: now.saturating_add(self.timeout),
+                                    });
+        MpidEnvelope::new(request_id, request)
+    }
+
+    /// If `response` carries the ID of a pending request, that request is no longer pending and is
+    /// returned along with the response.  Otherwise `response` is returned in the `Err`, since it
+    /// is either unsolicited or a late reply to a request which has already timed out.
+    pub fn receive(&mut self,
+                   response: MpidEnvelope)
+                   -> Result<(MpidMessageWrapper, MpidMessageWrapper), MpidEnvelope> {
+        match self.pending.remove(&response.request_id()) {
+            Some(pending) => Ok((pending.request, response.into_message())),
+            None => Err(response),
+        }
+    }
+
+    /// Removes and returns the ID and message of every pending request which has timed out by
+    /// `now`.
+    pub fn expire(&mut self, now: u64) -> Vec<(u64, MpidMessageWrapper)> {
+        let expired_ids = self.pending
+                              .iter()
+                              .filter(|&(_, pending)| pending.expires_at <= now)
+                              .map(|(request_id, _)| *request_id)
+                              .collect::<Vec<_>>();
+        expired_ids.into_iter()
+                   .filter_map(|request_id| {
+                       self.pending
+                           .remove(&request_id)
+                           .map(|pending| (request_id, pending.request))
+                   })
+                   .collect()
+    }
+
+    /// Whether the request with the given ID is still awaiting a response.
+    pub fn is_pending(&self, request_id: u64) -> bool {
+        self.pending.contains_key(&request_id)
+    }
+
+    /// The number of requests awaiting a response.
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand;
+    use xor_name::XorName;
+    use {HeaderPage, MpidMessageWrapper, PageRequest};
+
+    #[test]
+    fn full() {
+        let mut tracker = RequestTracker::new(10);
+        let page_request = PageRequest::first(10);
+        let page = unwrap_result!(HeaderPage::new(&[], &page_request));
+        let name: XorName = rand::random();
+
+        let outbox_has = tracker.send(MpidMessageWrapper::OutboxHas(vec![name]), 100);
+        let get_headers = MpidMessageWrapper::GetOutboxHeadersPage(page_request.clone());
+        let get_headers = tracker.send(get_headers, 105);
+        assert!(outbox_has.request_id() != get_headers.request_id());
+        assert_eq!(tracker.pending_count(), 2);
+
+        // Responses arriving out of order are paired with the correct requests.
+        let headers_response =
+            get_headers.response(MpidMessageWrapper::GetOutboxHeadersPageResponse(page.clone()));
+        let (request, response) = unwrap_result!(tracker.receive(headers_response.clone()));
+        assert_eq!(request, MpidMessageWrapper::GetOutboxHeadersPage(page_request));
+        assert_eq!(response, MpidMessageWrapper::GetOutboxHeadersPageResponse(page));
+        assert!(!tracker.is_pending(get_headers.request_id()));
+        assert!(tracker.is_pending(outbox_has.request_id()));
+
+        // A repeated response is no longer matched.
+        assert_eq!(tracker.receive(headers_response.clone()).err(),
+                   Some(headers_response));
+
+        // The remaining request times out.
+        assert!(tracker.expire(109).is_empty());
+        let expired = tracker.expire(110);
+        assert_eq!(expired,
+                   vec![(outbox_has.request_id(), MpidMessageWrapper::OutboxHas(vec![name]))]);
+        assert_eq!(tracker.pending_count(), 0);
+
+        // A late response is not matched.
+        let late_response = outbox_has.response(MpidMessageWrapper::OutboxHasResponse(vec![]));
+        assert!(tracker.receive(late_response).is_err());
+    }
+}
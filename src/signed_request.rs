@@ -0,0 +1,300 @@
+// Copyright 2016 MaidSafe.net limited.
+//
+// This SAFE Network Software is licensed to you under (1) the MaidSafe.net Commercial License,
+// version 1.0 or later, or (2) The General Public License (GPL), version 3, depending on which
+// licence you accepted on initial access to the Software (the "Licences").
+//
+// By contributing code to the SAFE Network Software, or to this project generally, you agree to be
+// bound by the terms of the MaidSafe Contributor Agreement, version 1.0.  This, along with the
+// Licenses can be found in the root directory of this project at LICENSE, COPYING and CONTRIBUTOR.
+//
+// Unless required by applicable law or agreed to in writing, the SAFE Network Software distributed
+// under the GPL Licence is distributed on an "AS IS" BASIS, WITHOUT WARRANTIES OR CONDITIONS OF ANY
+// KIND, either express or implied.
+//
+// Please review the Licences for the specific language governing permissions and limitations
+// relating to use of the SAFE Network Software.
+
+use std::collections::HashMap;
+
+use maidsafe_utilities::serialisation::serialise;
+use sodiumoxide::crypto::sign::{self, PublicKey, SecretKey, Signature};
+use super::{Error, MpidHeader, MpidMessageWrapper};
+use xor_name::XorName;
+
+/// A request from a Client to MpidManagers, signed by the Client so that the managers can
+/// authenticate it and reject replays.
+///
+/// The signature covers the request and a nonce which must increase with every request signed by
+/// the same key, e.g. a counter or a timestamp with sub-second resolution.
+#[derive(PartialEq, Eq, Hash, Clone, Debug, RustcDecodable, RustcEncodable)]
+pub struct SignedRequest {
+    public_key: PublicKey,
+    nonce: u64,
+    request: Box<MpidMessageWrapper>,
+    signature: Signature,
+}
+
+impl SignedRequest {
+    /// Constructor.  `public_key` and `secret_key` are the Client's signing keys.
+    pub fn new(request: MpidMessageWrapper,
+               nonce: u64,
+               public_key: &PublicKey,
+               secret_key: &SecretKey)
+               -> Result<SignedRequest, Error> {
+        let encoded = try!(serialise(&(public_key, nonce, &request)));
+        Ok(SignedRequest {
+            public_key: *public_key,
+            nonce: nonce,
+            request: Box::new(request),
+            signature: sign::sign_detached(&encoded, secret_key),
+        })
+    }
+
+    /// The name of the Client which signed the request, as per
+    /// [`name_from_public_key()`](fn.name_from_public_key.html).
+    pub fn requester(&self) -> XorName {
+        ::name_from_public_key(&self.public_key)
+    }
+
+    /// Getter for the Client's public key.
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    /// Getter for the nonce.
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    /// Getter for the signed request.
+    pub fn request(&self) -> &MpidMessageWrapper {
+        &self.request
+    }
+
+    /// Consumes the `SignedRequest`, returning the signed request.
+    pub fn into_request(self) -> MpidMessageWrapper {
+        *self.request
+    }
+
+    /// Validates the signature against the public key carried in the request.
+    pub fn verify(&self) -> bool {
+        match serialise(&(&self.public_key, self.nonce, &*self.request)) {
+            Ok(encoded) => sign::verify_detached(&self.signature, &encoded, &self.public_key),
+            Err(_) => false,
+        }
+    }
+
+    /// Used by the receiver's MpidManagers on receipt of a `RecallHeader` to check that this is a
+    /// validly-signed `Recall` of `header`, made by `sender`, the account whose MpidManagers
+    /// delivered the header.
+    ///
+    /// Unless `header` has a sealed sender, `sender` must also be the header's sender.
+    pub fn authorises_recall(&self, header: &MpidHeader, sender: &XorName) -> bool {
+        let name = match *self.request {
+            MpidMessageWrapper::Recall(ref name) => name,
+            _ => return false,
+        };
+        match header.name() {
+            Ok(header_name) => {
+                *name == header_name && *sender == self.requester() &&
+                header.sender().map_or(true, |header_sender| header_sender == sender) &&
+                self.verify()
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// Used by MpidManagers to authenticate [signed requests](struct.SignedRequest.html) and to reject
+/// replays of requests already handled.
+///
+/// The last nonce accepted from each Client is only held in memory.  To keep rejecting replays
+/// across restarts, a request should instead be [checked](#method.check), then have its nonce
+/// persisted and [recorded](#method.record), and the persisted nonces recorded again on startup.
+pub struct RequestVerifier {
+    last_nonces: HashMap<XorName, u64>,
+}
+
+impl RequestVerifier {
+    /// Constructor.
+    pub fn new() -> RequestVerifier {
+        RequestVerifier { last_nonces: HashMap::new() }
+    }
+
+    /// Verifies `request` and returns the name of the Client which signed it.  The caller is
+    /// responsible for checking that this Client is authorised to make the request, e.g. that it
+    /// owns the account being accessed.
+    ///
+    /// `Error::InvalidSignature` is returned if the signature is invalid, and `Error::Replayed` if
+    /// the nonce isn't greater than that of every request previously accepted from the same
+    /// Client.
+    pub fn verify(&mut self, request: &SignedRequest) -> Result<XorName, Error> {
+        let requester = try!(self.check(request));
+        self.record(requester.clone(), request.nonce);
+        Ok(requester)
+    }
+
+    /// As per [`verify()`](#method.verify), but without recording the request's nonce as accepted.
+    pub fn check(&self, request: &SignedRequest) -> Result<XorName, Error> {
+        if !request.verify() {
+            return Err(Error::InvalidSignature);
+        }
+        let requester = request.requester();
+        if let Some(last_nonce) = self.last_nonces.get(&requester) {
+            if request.nonce <= *last_nonce {
+                return Err(Error::Replayed);
+            }
+        }
+        Ok(requester)
+    }
+
+    /// Records `nonce` as accepted from `requester`, e.g. once it has been persisted, or when
+    /// restoring nonces persisted before a restart, so that only requests with a greater nonce are
+    /// subsequently accepted from it.  Has no effect if a greater nonce has already been accepted.
+    pub fn record(&mut self, requester: XorName, nonce: u64) {
+        let last_nonce = self.last_nonces.entry(requester).or_insert(nonce);
+        if *last_nonce < nonce {
+            *last_nonce = nonce;
+        }
+    }
+}
+
+impl Default for RequestVerifier {
+    fn default() -> RequestVerifier {
+        RequestVerifier::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand;
+    use sodiumoxide::crypto::{box_, sign};
+    use xor_name::XorName;
+    use {Error, MpidHeader, MpidMessageWrapper};
+
+    #[test]
+    fn full() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let (other_public_key, other_secret_key) = sign::gen_keypair();
+        let name: XorName = rand::random();
+        let mut verifier = RequestVerifier::new();
+
+        let request = unwrap_result!(SignedRequest::new(MpidMessageWrapper::DeleteMessage(name),
+                                                        1,
+                                                        &public_key,
+                                                        &secret_key));
+        assert!(request.verify());
+        assert_eq!(unwrap_result!(verifier.verify(&request)),
+                   ::name_from_public_key(&public_key));
+
+        // Replays, and requests with an old nonce, are rejected.
+        match verifier.verify(&request) {
+            Err(Error::Replayed) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        let request = unwrap_result!(SignedRequest::new(MpidMessageWrapper::DeleteHeader(name),
+                                                        1,
+                                                        &public_key,
+                                                        &secret_key));
+        assert!(verifier.verify(&request).is_err());
+        let request = unwrap_result!(SignedRequest::new(MpidMessageWrapper::DeleteHeader(name),
+                                                        2,
+                                                        &public_key,
+                                                        &secret_key));
+        assert!(verifier.verify(&request).is_ok());
+
+        // Nonces are tracked per Client.
+        let delete_message = MpidMessageWrapper::DeleteMessage(name);
+        let other_request = unwrap_result!(SignedRequest::new(delete_message,
+                                                              1,
+                                                              &other_public_key,
+                                                              &other_secret_key));
+        assert_eq!(unwrap_result!(verifier.verify(&other_request)),
+                   ::name_from_public_key(&other_public_key));
+
+        // Checking a request doesn't record its nonce, whereas recording one rejects requests up
+        // to it.
+        let mut restored = RequestVerifier::new();
+        assert!(restored.check(&request).is_ok());
+        assert!(restored.check(&request).is_ok());
+        restored.record(::name_from_public_key(&public_key), 2);
+        restored.record(::name_from_public_key(&public_key), 1);
+        match restored.check(&request) {
+            Err(Error::Replayed) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        assert!(restored.verify(&other_request).is_ok());
+
+        // Tampering with the request or substituting the key invalidates the signature.
+        let mut tampered = request.clone();
+        tampered.nonce = 3;
+        assert!(!tampered.verify());
+        let mut tampered = request.clone();
+        tampered.public_key = other_public_key;
+        match verifier.verify(&tampered) {
+            Err(Error::InvalidSignature) => (),
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn authorises_recall() {
+        let (public_key, secret_key) = sign::gen_keypair();
+        let (other_public_key, other_secret_key) = sign::gen_keypair();
+        let sender = ::name_from_public_key(&public_key);
+        let recipient: XorName = rand::random();
+        let header = unwrap_result!(MpidHeader::new(sender.clone(),
+                                                    vec![],
+                                                    recipient.clone(),
+                                                    &[],
+                                                    None,
+                                                    &secret_key));
+        let header_name = unwrap_result!(header.name());
+
+        let recall = unwrap_result!(SignedRequest::new(MpidMessageWrapper::Recall(header_name),
+                                                       1,
+                                                       &public_key,
+                                                       &secret_key));
+        assert!(recall.authorises_recall(&header, &sender));
+        assert!(!recall.authorises_recall(&header, &recipient));
+
+        // Only the header's sender can recall it, and only via a `Recall` naming that header.
+        let recall = unwrap_result!(SignedRequest::new(MpidMessageWrapper::Recall(header_name),
+                                                       1,
+                                                       &other_public_key,
+                                                       &other_secret_key));
+        let other_sender = ::name_from_public_key(&other_public_key);
+        assert!(!recall.authorises_recall(&header, &sender));
+        assert!(!recall.authorises_recall(&header, &other_sender));
+        let recall = unwrap_result!(SignedRequest::new(MpidMessageWrapper::Recall(recipient),
+                                                       1,
+                                                       &public_key,
+                                                       &secret_key));
+        assert!(!recall.authorises_recall(&header, &sender));
+        let delete_header = MpidMessageWrapper::DeleteHeader(header_name);
+        let delete = unwrap_result!(SignedRequest::new(delete_header,
+                                                       1,
+                                                       &public_key,
+                                                       &secret_key));
+        assert!(!delete.authorises_recall(&header, &sender));
+
+        // A header with a sealed sender can be recalled by the account which delivered it.
+        let (recipient_public_key, _) = box_::gen_keypair();
+        let header = unwrap_result!(MpidHeader::new_with_sealed_sender(sender.clone(),
+                                                                       vec![],
+                                                                       recipient,
+                                                                       &[],
+                                                                       None,
+                                                                       &recipient_public_key,
+                                                                       &secret_key));
+        let header_name = unwrap_result!(header.name());
+        let recall = unwrap_result!(SignedRequest::new(MpidMessageWrapper::Recall(header_name),
+                                                       2,
+                                                       &public_key,
+                                                       &secret_key));
+        assert!(recall.authorises_recall(&header, &sender));
+        assert!(!recall.authorises_recall(&header, &other_sender));
+    }
+}